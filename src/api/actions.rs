@@ -1,18 +1,19 @@
-use std::{env, io::Error};
-
-use super::client::LoanClient;
+use super::client::{parse_url, required, LoanClient};
+use super::config::ConfigError;
+use super::error::{Result, SupremoError};
+use super::secrets::{EnvProvider, KeyKind, SecretProvider};
 
 /// # Examples
 /// ```
 /// use supremo_loan::api::actions::{create_clients, add_clients_keys};
 /// use supremo_loan::api::client::LoanClient;
-/// use std::{io::Error, env};
+/// use std::env;
 /// fn main(){
 ///     let clients_json = serde_json::json!([{
-///         "base_url": "base_url",
+///         "base_url": "https://lender.example.com",
 ///         "name" : "bank_name",
 ///         "logo_url" : "logo_url",
-///         "redirect_url" : "redirect_url"
+///         "redirect_url" : "https://app.example.com/callback"
 ///     }]);
 ///     env::set_var("BANK_NAME_SECRET_KEY", "secret_key");
 ///     env::set_var("BANK_NAME_PUBLIC_KEY", "public_key");
@@ -21,7 +22,7 @@ use super::client::LoanClient;
 ///     let clients_json = serde_json::to_string(&clients).unwrap();
 ///     assert_eq!(
 ///         clients_json,
-///         r#"[{"base_url":"base_url","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"redirect_url"}]"#
+///         r#"[{"base_url":"https://lender.example.com","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"https://app.example.com/callback"}]"#
 ///     );
 /// }
 /// ```
@@ -30,182 +31,290 @@ use super::client::LoanClient;
 /// ```
 /// use supremo_loan::api::actions::create_clients;
 /// use supremo_loan::api::client::LoanClient;
-/// use std::io::Error;
-/// fn main() -> Result<(), Error> {
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
 ///     let clients_json =serde_json::json!([{
-///             "base_url": "base_url",
+///             "base_url": "https://lender.example.com",
 ///             "secret_key" : "secret_key",
 ///             "public_key" : "public_key",
 ///             "name" : "bank_name",
 ///             "logo_url" : "logo_url",
-///             "redirect_url" : "redirect_url"
+///             "redirect_url" : "https://app.example.com/callback"
 ///         }]);
 ///         
 ///     let clients = create_clients(&clients_json)?;
 ///     let clients_json =  serde_json::to_string(&clients)?;
 ///     assert_eq!(
 ///       clients_json,
-///       r#"[{"base_url":"base_url","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"redirect_url"}]"#
+///       r#"[{"base_url":"https://lender.example.com","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"https://app.example.com/callback"}]"#
 ///     );
 ///     Ok(())
 /// }
 /// ```
-
-pub fn create_client(client_json: &serde_json::Value) -> Result<LoanClient, Error> {
-    let base_url = client_json["base_url"]
-        .as_str()
-        .unwrap_or_else(|| panic!("base_url is not string"))
-        .to_string();
-    let secret_key = client_json["secret_key"]
-        .as_str()
-        .unwrap_or_else(|| panic!("base_url is not string"))
-        .to_string();
-    let public_key = client_json["public_key"]
-        .as_str()
-        .unwrap_or_else(|| panic!("base_url is not string"))
-        .to_string();
-    let name = client_json["name"]
-        .as_str()
-        .unwrap_or_else(|| panic!("base_url is not string"))
-        .to_string();
-    let logo_url = client_json["logo_url"]
-        .as_str()
-        .unwrap_or_else(|| panic!("base_url is not string"))
-        .to_string();
-
-    let redirect_url = client_json["redirect_url"]
-        .as_str()
-        .unwrap_or_else(|| panic!("base_url is not string"))
-        .to_string();
-
-    let client = LoanClient::new(
-        base_url,
-        secret_key,
-        public_key,
-        name,
-        logo_url,
-        redirect_url,
-    );
-    Ok(client)
+pub fn create_client(client_json: &serde_json::Value) -> Result<LoanClient> {
+    client_from_json(client_json, None).map_err(|errors| invalid_clients(&errors))
 }
 
-pub fn create_clients(clients_json: &serde_json::Value) -> Result<Vec<LoanClient>, Error> {
-    if clients_json.is_array() {
-        let clients = clients_json
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|client| {
-                let base_url = client["base_url"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("base_url is not string"))
-                    .to_string();
-                let secret_key = client["secret_key"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("secret_key is not string"))
-                    .to_string();
-                let public_key = client["public_key"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("public_key is not string"))
-                    .to_string();
-                let name = client["name"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("name is not string"))
-                    .to_string();
-                let logo_url = client["logo_url"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("logo_url is not string"))
-                    .to_string();
-
-                let redirect_url = client["redirect_url"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("redirect_url is not string"))
-                    .to_string();
-
-                LoanClient::new(
-                    base_url,
-                    secret_key,
-                    public_key,
-                    name,
-                    logo_url,
-                    redirect_url,
-                )
-            })
-            .collect();
-        Ok(clients)
+/// build every client, failing with a message that lists each invalid lender and field
+/// when any entry is invalid, use [`create_clients_lenient`] to keep the valid ones
+pub fn create_clients(clients_json: &serde_json::Value) -> Result<Vec<LoanClient>> {
+    let created = create_clients_lenient(clients_json)?;
+    if created.errors.is_empty() {
+        Ok(created.clients)
     } else {
-        Err(Error::new(
-            std::io::ErrorKind::Other,
-            "clients_json is not array try to use ```create_client``` function instead",
-        ))
+        Err(invalid_clients(&created.errors))
+    }
+}
+
+/// Result of [`create_clients_lenient`].
+#[derive(Debug)]
+pub struct CreatedClients {
+    /// clients of the valid entries, in order
+    pub clients: Vec<LoanClient>,
+    /// every invalid field of the skipped entries
+    pub errors: Vec<ConfigError>,
+}
+
+/// build the clients of the valid entries and report the invalid ones instead of failing
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::actions::create_clients_lenient;
+///
+/// let clients_json = serde_json::json!([
+///     {"base_url": "https://a.example.com", "secret_key": "secret_key", "public_key": "public_key",
+///      "name": "bank a", "redirect_url": "https://app.example.com/callback"},
+///     {"base_url": 1, "name": "bank b"},
+/// ]);
+/// let created = create_clients_lenient(&clients_json).unwrap();
+/// assert_eq!(created.clients.len(), 1);
+/// let fields: Vec<_> = created.errors.iter().map(|e| e.path.as_str()).collect();
+/// assert_eq!(
+///     fields,
+///     ["[1].base_url", "[1].secret_key", "[1].public_key", "[1].redirect_url"]
+/// );
+/// ```
+pub fn create_clients_lenient(clients_json: &serde_json::Value) -> Result<CreatedClients> {
+    let entries = clients_json.as_array().ok_or_else(|| {
+        SupremoError::Config(
+            "clients_json is not array try to use ```create_client``` function instead".to_string(),
+        )
+    })?;
+
+    let mut created = CreatedClients {
+        clients: Vec::new(),
+        errors: Vec::new(),
+    };
+    for (index, entry) in entries.iter().enumerate() {
+        match client_from_json(entry, Some(index)) {
+            Ok(client) => created.clients.push(client),
+            Err(errors) => created.errors.extend(errors),
+        }
     }
+    Ok(created)
 }
 
-pub fn add_clients_keys(clients_json: &serde_json::Value) -> Result<serde_json::Value, Error> {
-    // add secret_key and public_key to clients
-    if clients_json.is_array() {
-        let clients = clients_json
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|client| {
-                // add secret_key and public_key to client
-                let mut client = client.clone(); //TODO::find a way not to clone
-                let name = client["name"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("name is not string"))
-                    .to_string()
-                    .replace(" ", "_")
-                    .to_uppercase();
-
-                client["secret_key"] = env::var(format!("{}_SECRET_KEY", name))
-                    .unwrap_or_else(|_| panic!("env value {}_SECRET_KEY is not set", name))
-                    .into();
-                client["public_key"] = env::var(format!("{}_PUBLIC_KEY", name))
-                    .unwrap_or_else(|_| panic!("env value {}_PUBLIC_KEY is not set", name))
-                    .into();
-                Ok(client)
-            })
-            .collect::<Result<serde_json::Value, Error>>()?;
-        Ok(clients)
+/// build one client, collecting every invalid field instead of stopping at the first
+fn client_from_json(
+    client_json: &serde_json::Value,
+    lender: Option<usize>,
+) -> std::result::Result<LoanClient, Vec<ConfigError>> {
+    let mut errors = Vec::new();
+    if !client_json.is_object() {
+        errors.push(field_error(lender, None, "must be an object".to_string()));
+        return Err(errors);
+    }
+
+    let mut field = |name: &str, required: bool| match &client_json[name] {
+        serde_json::Value::String(value) => Some(value.clone()),
+        serde_json::Value::Null if !required => None,
+        serde_json::Value::Null => {
+            errors.push(field_error(lender, Some(name), "is missing".to_string()));
+            None
+        }
+        other => {
+            errors.push(field_error(
+                lender,
+                Some(name),
+                format!("must be a string, found {}", other),
+            ));
+            None
+        }
+    };
+
+    let base_url = field("base_url", true);
+    let secret_key = field("secret_key", true);
+    let public_key = field("public_key", true);
+    let name = field("name", true);
+    let logo_url = field("logo_url", false);
+    let redirect_url = field("redirect_url", true);
+
+    // LoanClientBuilder::build stops at the first invalid value, check each of them first
+    let mut check = |field: &str, result: Result<()>| {
+        if let Err(e) = result {
+            errors.push(builder_error(lender, field, e));
+        }
+    };
+    for (field, value) in [("base_url", &base_url), ("redirect_url", &redirect_url)] {
+        if let Some(value) = value {
+            check(field, parse_url(field, Some(value.clone())).map(drop));
+        }
+    }
+    for (field, value) in [
+        ("secret_key", &secret_key),
+        ("public_key", &public_key),
+        ("name", &name),
+    ] {
+        if let Some(value) = value {
+            check(field, required(field, Some(value.clone())).map(drop));
+        }
+    }
+
+    match (base_url, secret_key, public_key, name, redirect_url) {
+        (Some(base_url), Some(secret_key), Some(public_key), Some(name), Some(redirect_url))
+            if errors.is_empty() =>
+        {
+            let mut builder = LoanClient::builder()
+                .base_url(base_url)
+                .secret_key(secret_key)
+                .public_key(public_key)
+                .name(name)
+                .redirect_url(redirect_url);
+            if let Some(logo_url) = logo_url {
+                builder = builder.logo_url(logo_url);
+            }
+            builder
+                .build()
+                .map_err(|e| vec![field_error(lender, None, e.to_string())])
+        }
+        _ => Err(errors),
+    }
+}
+
+/// error of the builder about `field`, without the field name its message starts with
+fn builder_error(lender: Option<usize>, field: &str, error: SupremoError) -> ConfigError {
+    let message = match error {
+        SupremoError::Config(message) => match message.strip_prefix(field) {
+            Some(rest) => rest.trim_start().to_string(),
+            None => message,
+        },
+        error => error.to_string(),
+    };
+    field_error(lender, Some(field), message)
+}
+
+fn field_error(lender: Option<usize>, field: Option<&str>, message: String) -> ConfigError {
+    let path = match (lender, field) {
+        (Some(index), Some(field)) => format!("[{}].{}", index, field),
+        (Some(index), None) => format!("[{}]", index),
+        (None, Some(field)) => field.to_string(),
+        (None, None) => String::new(),
+    };
+    ConfigError {
+        lender,
+        path,
+        line: None,
+        column: None,
+        message,
+    }
+}
+
+fn invalid_clients(errors: &[ConfigError]) -> SupremoError {
+    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    SupremoError::Config(format!("invalid clients: {}", errors.join("; ")))
+}
+
+/// add `secret_key` and `public_key` to every client from the `{NAME}_SECRET_KEY` and
+/// `{NAME}_PUBLIC_KEY` environment variables, failing with every missing key at once
+pub fn add_clients_keys(clients_json: &serde_json::Value) -> Result<serde_json::Value> {
+    add_clients_keys_with(clients_json, &EnvProvider::default())
+}
+
+/// like [`add_clients_keys`], looking the keys up in `secrets`
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::actions::add_clients_keys_with;
+/// use supremo_loan::api::secrets::DotenvProvider;
+///
+/// let secrets = DotenvProvider::parse("BANK_NAME_SECRET_KEY=secret_key\nBANK_NAME_PUBLIC_KEY=public_key");
+/// let clients = add_clients_keys_with(&serde_json::json!([{"name": "bank name"}]), &secrets).unwrap();
+/// assert_eq!(clients[0]["secret_key"], "secret_key");
+/// ```
+pub fn add_clients_keys_with(
+    clients_json: &serde_json::Value,
+    secrets: &dyn SecretProvider,
+) -> Result<serde_json::Value> {
+    let entries = clients_json.as_array().ok_or_else(|| {
+        SupremoError::Config(
+            "clients_json is not array try to use ```create_client``` function instead".to_string(),
+        )
+    })?;
+
+    let mut errors = Vec::new();
+    let mut clients = Vec::with_capacity(entries.len());
+    for (index, client) in entries.iter().enumerate() {
+        // add secret_key and public_key to client
+        let mut client = client.clone(); //TODO::find a way not to clone
+        let Some(name) = client["name"].as_str().map(str::to_string) else {
+            errors.push(field_error(
+                Some(index),
+                Some("name"),
+                "must be a string".to_string(),
+            ));
+            clients.push(client);
+            continue;
+        };
+
+        for kind in [KeyKind::SecretKey, KeyKind::PublicKey] {
+            match secrets.secret(&name, kind) {
+                Ok(Some(key)) => client[kind.as_str()] = key.expose().into(),
+                Ok(None) => errors.push(field_error(
+                    Some(index),
+                    Some(kind.as_str()),
+                    format!("{} is not set", secrets.location(&name, kind)),
+                )),
+                Err(e) => errors.push(field_error(Some(index), Some(kind.as_str()), e.to_string())),
+            }
+        }
+        clients.push(client);
+    }
+
+    if errors.is_empty() {
+        Ok(serde_json::Value::Array(clients))
     } else {
-        Err(Error::new(
-            std::io::ErrorKind::Other,
-            "clients_json is not array try to use ```create_client``` function instead",
-        ))
+        Err(invalid_clients(&errors))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::api::actions::{add_clients_keys, create_client, create_clients};
     use std::env;
-
-    use crate::api::actions::{add_clients_keys, create_clients};
     #[test]
     fn new_json_clients() {
         let clients_json = serde_json::json!([{
-            "base_url": "base_url",
+            "base_url": "https://lender.example.com",
             "secret_key" : "secret_key",
             "public_key" : "public_key",
             "name" : "bank_name",
             "logo_url" : "logo_url",
-            "redirect_url" : "redirect_url"
+            "redirect_url" : "https://app.example.com/callback"
         }]);
 
         let clients = create_clients(&clients_json).unwrap();
         let clients_json = serde_json::to_string(&clients).unwrap();
         assert_eq!(
             clients_json,
-            r#"[{"base_url":"base_url","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"redirect_url"}]"#
+            r#"[{"base_url":"https://lender.example.com","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"https://app.example.com/callback"}]"#
         );
     }
     #[test]
     fn add_keys_json_clients() {
         let clients_json = serde_json::json!([{
-            "base_url": "base_url",
+            "base_url": "https://lender.example.com",
             "name" : "bank name",
             "logo_url" : "logo_url",
-            "redirect_url" : "redirect_url"
+            "redirect_url" : "https://app.example.com/callback"
         }]);
         env::set_var("BANK_NAME_SECRET_KEY", "secret_key");
         env::set_var("BANK_NAME_PUBLIC_KEY", "public_key");
@@ -214,7 +323,57 @@ mod tests {
         let clients_json = serde_json::to_string(&clients).unwrap();
         assert_eq!(
             clients_json,
-            r#"[{"base_url":"base_url","public_key":"public_key","name":"bank name","logo_url":"logo_url","redirect_url":"redirect_url"}]"#
+            r#"[{"base_url":"https://lender.example.com","public_key":"public_key","name":"bank name","logo_url":"logo_url","redirect_url":"https://app.example.com/callback"}]"#
         );
     }
+
+    #[test]
+    fn invalid_clients_are_reported_together() {
+        let clients_json = serde_json::json!([
+            {
+                "base_url": "https://lender.example.com",
+                "secret_key" : "secret_key",
+                "public_key" : "public_key",
+                "name" : "bank_name",
+                "redirect_url" : "https://app.example.com/callback"
+            },
+            {
+                "base_url": "https://lender.example.com",
+                "secret_key" : 42,
+                "name" : "other_bank",
+                "redirect_url" : "https://app.example.com/callback"
+            },
+            "not a client"
+        ]);
+
+        let err = create_clients(&clients_json).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: invalid clients: [1].secret_key: must be a string, found 42; \
+             [1].public_key: is missing; [2]: must be an object"
+        );
+
+        let err = create_client(&serde_json::json!({"name": "bank_name"})).unwrap_err();
+        assert!(err.to_string().contains("base_url: is missing"));
+
+        let err = create_client(&serde_json::json!({
+            "base_url": "",
+            "secret_key": "",
+            "public_key": "public_key",
+            "name": "bank_name",
+            "redirect_url": "ftp://app.example.com"
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: invalid clients: base_url: must not be empty; \
+             redirect_url: \"ftp://app.example.com\" must be an http(s) url; \
+             secret_key: must not be empty"
+        );
+
+        let err = add_clients_keys(&serde_json::json!([{"name": "unset bank"}])).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("[0].secret_key: env value UNSET_BANK_SECRET_KEY is not set"));
+    }
 }
@@ -0,0 +1,241 @@
+//! Blocking version of [`LoanClient`](super::client::LoanClient) for code that does not
+//! run inside an async runtime, such as scripts and synchronous workers.
+//!
+//! The blocking client drives the async client on its own single threaded runtime, so
+//! it shares the same request and response types, errors, retries and token handling.
+//! It must not be used from within an async runtime, call the async client there.
+
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+
+use super::client;
+use super::error::{Result, SupremoError};
+use super::idempotency::IdempotencyKey;
+use super::oauth::AuthorizationRequest;
+use super::secrets::SecretString;
+use super::types::{
+    AccountChange, AnchorPagination, CalculateLonaResponse, ClientLimit, LinkedAccount, LoanCreted,
+    LoanInput, OuathToken, OuathUser, PaginatedAnchors, TokenIntrospection, TokenTypeHint,
+    UserSession, UserToken,
+};
+
+/// # Examples
+/// ```
+/// use supremo_loan::api::blocking::LoanClient;
+///
+/// let client = LoanClient::new(
+///     String::from("base_url"),
+///     String::from("secret_key"),
+///     String::from("public_key"),
+///     String::from("bank_name"),
+///     String::from("logo_url"),
+///     String::from("redirect_url"),
+/// )
+/// .unwrap();
+/// assert_eq!(client.inner().name, "bank_name");
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoanClient {
+    inner: client::LoanClient,
+    runtime: Arc<Runtime>,
+}
+
+impl LoanClient {
+    pub fn new(
+        base_url: String,
+        secret_key: String,
+        public_key: String,
+        name: String,
+        logo_url: String,
+        redirect_url: String,
+    ) -> Result<Self> {
+        Self::from_async(client::LoanClient::new(
+            base_url,
+            secret_key,
+            public_key,
+            name,
+            logo_url,
+            redirect_url,
+        ))
+    }
+
+    /// wrap a client built with [`client::LoanClient::builder`]
+    pub fn from_async(inner: client::LoanClient) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| SupremoError::Config(format!("could not start runtime: {}", e)))?;
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    pub fn inner(&self) -> &client::LoanClient {
+        &self.inner
+    }
+
+    pub fn bearer_token(&self) -> Result<SecretString> {
+        self.runtime.block_on(self.inner.bearer_token())
+    }
+
+    pub fn get_auth_token(&self) -> Result<OuathToken> {
+        self.runtime.block_on(self.inner.get_auth_token())
+    }
+
+    pub fn revoke_token(&self, token: &str, hint: Option<TokenTypeHint>) -> Result<()> {
+        self.runtime.block_on(self.inner.revoke_token(token, hint))
+    }
+
+    pub fn introspect_token(
+        &self,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<TokenIntrospection> {
+        self.runtime
+            .block_on(self.inner.introspect_token(token, hint))
+    }
+
+    pub fn get_user_info(&self, bearer_token: &str) -> Result<OuathUser> {
+        self.runtime
+            .block_on(self.inner.get_user_info(bearer_token))
+    }
+
+    pub fn authorization_url(&self, scope: Option<&str>) -> Result<AuthorizationRequest> {
+        self.inner.authorization_url(scope)
+    }
+
+    pub fn exchange_authorization_callback(
+        &self,
+        request: &AuthorizationRequest,
+        code: &str,
+        state: Option<&str>,
+    ) -> Result<UserSession> {
+        self.runtime.block_on(
+            self.inner
+                .exchange_authorization_callback(request, code, state),
+        )
+    }
+
+    pub fn exchange_code_auth(&self, code: &str) -> Result<UserSession> {
+        self.runtime.block_on(self.inner.exchange_code_auth(code))
+    }
+
+    pub fn link_account(&self, code: &str) -> Result<LinkedAccount> {
+        self.runtime.block_on(self.inner.link_account(code))
+    }
+
+    pub fn revalidate_account(&self, account: &mut LinkedAccount) -> Result<Vec<AccountChange>> {
+        self.runtime
+            .block_on(self.inner.revalidate_account(account))
+    }
+
+    pub fn refresh_user_token(&self, token: &UserToken) -> Result<UserToken> {
+        self.runtime.block_on(self.inner.refresh_user_token(token))
+    }
+
+    pub fn client_limit<'a>(
+        &self,
+        bearer_token: impl Into<Option<&'a str>>,
+        client_id: i32,
+    ) -> Result<ClientLimit> {
+        self.runtime
+            .block_on(self.inner.client_limit(bearer_token, client_id))
+    }
+
+    pub fn get_anchors<'a>(
+        &self,
+        bearer_token: impl Into<Option<&'a str>>,
+        client_id: i32,
+        pagination: Option<AnchorPagination>,
+    ) -> Result<PaginatedAnchors> {
+        self.runtime
+            .block_on(self.inner.get_anchors(bearer_token, client_id, pagination))
+    }
+
+    pub fn calculate_loan(
+        &self,
+        bearer_token: impl Into<Option<String>>,
+        body: Vec<LoanInput>,
+    ) -> Result<Vec<CalculateLonaResponse>> {
+        self.runtime
+            .block_on(self.inner.calculate_loan(bearer_token, body))
+    }
+
+    pub fn apply_for_loan(
+        &self,
+        bearer_token: impl Into<Option<String>>,
+        body: Vec<LoanInput>,
+    ) -> Result<LoanCreted> {
+        self.runtime
+            .block_on(self.inner.apply_for_loan(bearer_token, body))
+    }
+
+    pub fn apply_for_loan_with_key(
+        &self,
+        bearer_token: impl Into<Option<String>>,
+        body: Vec<LoanInput>,
+        idempotency_key: &IdempotencyKey,
+    ) -> Result<LoanCreted> {
+        self.runtime.block_on(self.inner.apply_for_loan_with_key(
+            bearer_token,
+            body,
+            idempotency_key,
+        ))
+    }
+}
+
+impl TryFrom<client::LoanClient> for LoanClient {
+    type Error = SupremoError;
+
+    fn try_from(inner: client::LoanClient) -> Result<Self> {
+        Self::from_async(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_without_a_caller_runtime() {
+        let client = LoanClient::new(
+            String::from("http://localhost:8080"),
+            String::from("secret_key"),
+            String::from("public_key"),
+            String::from("bank_name"),
+            String::from("logo_url"),
+            String::from("http://127.0.0.1:8020/"),
+        )
+        .unwrap();
+        let err = client.calculate_loan(None, vec![]).unwrap_err();
+        assert!(matches!(err, SupremoError::Validation(_)));
+        let err = client.client_limit(None, 1).unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+    }
+
+    #[cfg(feature = "actix")]
+    #[test]
+    fn round_trip_against_the_mock_lender() {
+        use crate::api::fake::{anchor, FakeLender};
+        use crate::api::mock_server::MockServer;
+
+        let lender = FakeLender::new()
+            .with_client(1, 10_000.0)
+            .with_anchor(1, anchor(3, 5_000.0))
+            .with_anchor(1, anchor(4, 5_000.0));
+        let server = MockServer::start(lender).unwrap();
+        let client = LoanClient::from_async(server.client()).unwrap();
+
+        let token = client.get_auth_token().unwrap().access_token;
+        let anchors = client
+            .get_anchors(token.expose(), 1, Some(AnchorPagination::default()))
+            .unwrap();
+        assert_eq!(anchors.total, 2);
+        assert_eq!(anchors.data[0].anchor_id, 4);
+
+        let err = client.get_anchors("expired", 1, None).unwrap_err();
+        assert!(err.is_auth_expired());
+    }
+}
@@ -0,0 +1,565 @@
+//! Record and replay of lender exchanges.
+//!
+//! A [`Cassette`] attached to a [`Transport`](super::transport::Transport) either records
+//! every request and response to a json file, or serves the recorded responses back
+//! without any network. Secrets (basic auth, bearer tokens, client secrets, tokens in
+//! bodies and authorization codes in requests) are redacted before anything is written,
+//! and incoming requests are redacted the same way before they are matched against the
+//! recording.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::error::{Result, SupremoError};
+pub use super::secrets::REDACTED;
+use super::transport::RawResponse;
+
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+const REDACTED_FIELDS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "token",
+    "code_verifier",
+    "client_secret",
+    "secret_key",
+    "password",
+];
+
+/// redacted in request bodies and queries only, in responses `code` is the lender's
+/// error code
+const REDACTED_REQUEST_FIELDS: &[&str] = &["code"];
+
+/// How a replayed request is matched against the recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// method, path, query and body must all be equal
+    Strict,
+    /// method and path must be equal, an interaction that also matches the query and
+    /// body is preferred
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay(MatchMode),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub query: Vec<(String, String)>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// json and form bodies as json, other bodies as a string
+    #[serde(default)]
+    pub body: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// json bodies as json, other bodies as a string
+    #[serde(default)]
+    pub body: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug)]
+struct Tape {
+    path: PathBuf,
+    mode: Mode,
+    interactions: Vec<Interaction>,
+    played: Vec<bool>,
+    headers: Vec<String>,
+    fields: Vec<String>,
+    request_fields: Vec<String>,
+}
+
+/// Recording of lender exchanges, attached with
+/// [`Transport::with_cassette`](super::transport::Transport::with_cassette).
+///
+/// Clones share the same recording.
+///
+/// # Examples
+/// ```no_run
+/// use supremo_loan::api::cassette::{Cassette, MatchMode};
+/// use supremo_loan::api::transport::Transport;
+///
+/// // in staging, record the exchanges
+/// let transport = Transport::default().with_cassette(Cassette::record("tests/cassettes/limits.json"));
+///
+/// // in ci, replay them
+/// let cassette = Cassette::replay("tests/cassettes/limits.json", MatchMode::Strict).unwrap();
+/// let transport = Transport::default().with_cassette(cassette);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    tape: Arc<Mutex<Tape>>,
+}
+
+impl Cassette {
+    /// record to `path`, the file is rewritten after every exchange
+    pub fn record(path: impl AsRef<Path>) -> Self {
+        Self::new(path.as_ref(), Mode::Record, Vec::new())
+    }
+
+    /// serve the exchanges recorded in `path`
+    pub fn replay(path: impl AsRef<Path>, match_mode: MatchMode) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| {
+            SupremoError::Config(format!("could not read cassette {}: {}", path.display(), e))
+        })?;
+        let file: CassetteFile = serde_json::from_str(&content).map_err(|e| {
+            SupremoError::Config(format!("invalid cassette {}: {}", path.display(), e))
+        })?;
+        Ok(Self::new(path, Mode::Replay(match_mode), file.interactions))
+    }
+
+    fn new(path: &Path, mode: Mode, interactions: Vec<Interaction>) -> Self {
+        Self {
+            tape: Arc::new(Mutex::new(Tape {
+                path: path.to_path_buf(),
+                mode,
+                played: vec![false; interactions.len()],
+                interactions,
+                headers: REDACTED_HEADERS.iter().map(|h| h.to_string()).collect(),
+                fields: REDACTED_FIELDS.iter().map(|f| f.to_string()).collect(),
+                request_fields: REDACTED_REQUEST_FIELDS
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+            })),
+        }
+    }
+
+    /// also redact the header `name`
+    pub fn redact_header(self, name: &str) -> Self {
+        self.tape().headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// also redact body fields and query parameters called `name`
+    pub fn redact_field(self, name: &str) -> Self {
+        self.tape().fields.push(name.to_string());
+        self
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.tape().interactions.clone()
+    }
+
+    /// recorded interactions that have not been replayed yet
+    pub fn unplayed(&self) -> Vec<Interaction> {
+        let tape = self.tape();
+        tape.interactions
+            .iter()
+            .zip(&tape.played)
+            .filter(|(_, played)| !**played)
+            .map(|(interaction, _)| interaction.clone())
+            .collect()
+    }
+
+    fn tape(&self) -> std::sync::MutexGuard<'_, Tape> {
+        self.tape.lock().unwrap()
+    }
+
+    pub(crate) async fn send(
+        &self,
+        client: &reqwest::Client,
+        request: reqwest::Request,
+    ) -> Result<RawResponse> {
+        let (mode, recorded_request) = {
+            let tape = self.tape();
+            (tape.mode, tape.request(&request))
+        };
+
+        match mode {
+            Mode::Replay(match_mode) => self.tape().play(&recorded_request, match_mode),
+            Mode::Record => {
+                let response = client.execute(request).await?;
+                let status = response.status();
+                let headers = response.headers().clone();
+                let body = response.bytes().await?.to_vec();
+                let raw = RawResponse {
+                    status,
+                    headers,
+                    body,
+                };
+                self.tape().append(recorded_request, &raw)?;
+                Ok(raw)
+            }
+        }
+    }
+}
+
+impl Tape {
+    fn request(&self, request: &reqwest::Request) -> RecordedRequest {
+        let url = request.url();
+        let query = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if self.is_redacted_field(&name, true) {
+                    REDACTED.to_string()
+                } else {
+                    value.into_owned()
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|bytes| self.redact(decode_body(request.headers(), bytes), true))
+            .unwrap_or(Value::Null);
+
+        RecordedRequest {
+            method: request.method().to_string(),
+            path: url.path().to_string(),
+            query,
+            headers: self.headers(request.headers()),
+            body,
+        }
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.headers.iter().any(|h| h == name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn is_redacted_field(&self, name: &str, request: bool) -> bool {
+        self.fields.iter().any(|field| field == name)
+            || request && self.request_fields.iter().any(|field| field == name)
+    }
+
+    /// redact the secrets of a request body when `request` is set, of a response body otherwise
+    fn redact(&self, value: Value, request: bool) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(name, value)| {
+                        let value = if self.is_redacted_field(&name, request) && !value.is_null() {
+                            Value::String(REDACTED.to_string())
+                        } else {
+                            self.redact(value, request)
+                        };
+                        (name, value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(|value| self.redact(value, request))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    fn append(&mut self, request: RecordedRequest, response: &RawResponse) -> Result<()> {
+        let body = if response.body.is_empty() {
+            Value::Null
+        } else {
+            self.redact(decode_body(&response.headers, &response.body), false)
+        };
+        self.interactions.push(Interaction {
+            request,
+            response: RecordedResponse {
+                status: response.status.as_u16(),
+                headers: self.headers(&response.headers),
+                body,
+            },
+        });
+        self.played.push(true);
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = CassetteFile {
+            interactions: self.interactions.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file).expect("cassettes serialize to json");
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| {
+                SupremoError::Config(format!("could not create {}: {}", parent.display(), e))
+            })?;
+        }
+        fs::write(&self.path, json).map_err(|e| {
+            SupremoError::Config(format!(
+                "could not write cassette {}: {}",
+                self.path.display(),
+                e
+            ))
+        })
+    }
+
+    fn play(&mut self, request: &RecordedRequest, match_mode: MatchMode) -> Result<RawResponse> {
+        let unplayed = |i: &usize| !self.played[*i];
+        let same_route = |i: &usize| {
+            let recorded = &self.interactions[*i].request;
+            recorded.method == request.method && recorded.path == request.path
+        };
+        let exact = |i: &usize| {
+            let recorded = &self.interactions[*i].request;
+            same_route(i) && recorded.query == request.query && recorded.body == request.body
+        };
+
+        let indexes = 0..self.interactions.len();
+        let found = match match_mode {
+            MatchMode::Strict => indexes.filter(unplayed).find(exact),
+            MatchMode::Lenient => indexes
+                .clone()
+                .filter(unplayed)
+                .find(exact)
+                .or_else(|| indexes.filter(unplayed).find(same_route)),
+        };
+        let index = found.ok_or_else(|| {
+            SupremoError::Config(format!(
+                "no recorded interaction in {} matches {} {}",
+                self.path.display(),
+                request.method,
+                request.path
+            ))
+        })?;
+        self.played[index] = true;
+        self.interactions[index].response.to_raw()
+    }
+}
+
+impl RecordedResponse {
+    fn to_raw(&self) -> Result<RawResponse> {
+        let status = StatusCode::from_u16(self.status).map_err(|e| {
+            SupremoError::Config(format!("invalid recorded status {}: {}", self.status, e))
+        })?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        let body = match &self.body {
+            Value::Null => Vec::new(),
+            Value::String(text) if !is_json(&headers) => text.clone().into_bytes(),
+            body => serde_json::to_vec(body).expect("json values serialize"),
+        };
+        Ok(RawResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"))
+}
+
+/// json and form bodies as json, anything else as a string
+fn decode_body(headers: &HeaderMap, bytes: &[u8]) -> Value {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return Value::Object(
+            form_urlencoded::parse(bytes)
+                .map(|(name, value)| (name.into_owned(), Value::String(value.into_owned())))
+                .collect(),
+        );
+    }
+    serde_json::from_slice(bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cassette_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "supremo-cassette-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn redacts_nested_fields() {
+        let cassette = Cassette::record(cassette_path("redact")).redact_field("email");
+        let tape = cassette.tape();
+        let body = tape.redact(
+            serde_json::json!({
+                "access_token": "secret",
+                "user": {"email": "borrower@example.com", "id": 1},
+                "scope": "read",
+            }),
+            false,
+        );
+        assert_eq!(body["access_token"], REDACTED);
+        assert_eq!(body["user"]["email"], REDACTED);
+        assert_eq!(body["user"]["id"], 1);
+        assert_eq!(body["scope"], "read");
+
+        // authorization codes are sent in requests, error codes come back in responses
+        let code = serde_json::json!({"code": "insufficient_limit"});
+        assert_eq!(tape.redact(code.clone(), true)["code"], REDACTED);
+        assert_eq!(tape.redact(code, false)["code"], "insufficient_limit");
+    }
+
+    // the remaining tests record and replay against a local mock lender
+    #[cfg(feature = "actix")]
+    mod replay {
+        use super::*;
+        use crate::api::error::ApiErrorKind;
+        use crate::api::fake::{anchor, Failure, FakeLender};
+        use crate::api::mock_server::{MockServer, MOCK_PUBLIC_KEY, MOCK_SECRET_KEY};
+        use crate::api::retry::Endpoint;
+        use crate::api::transport::Transport;
+        use crate::api::types::AnchorPagination;
+
+        fn pagination(page: i32) -> Option<AnchorPagination> {
+            Some(AnchorPagination {
+                page: Some(page),
+                page_size: Some(1),
+                order: Some("id".to_string()),
+            })
+        }
+
+        #[tokio::test]
+        async fn records_redacted_and_replays() {
+            let path = cassette_path("replay");
+            let lender = FakeLender::new()
+                .with_client(1, 10_000.0)
+                .with_anchor(1, anchor(3, 5_000.0))
+                .with_anchor(1, anchor(4, 5_000.0));
+            let server = MockServer::start(lender).unwrap();
+            let base_url = server.base_url();
+            let recording = server
+                .client()
+                .with_transport(Transport::default().with_cassette(Cassette::record(&path)));
+
+            let token = recording.get_auth_token().await.unwrap().access_token;
+            let first = recording
+                .get_anchors(token.expose(), 1, pagination(1))
+                .await
+                .unwrap();
+            recording
+                .get_anchors(token.expose(), 1, pagination(2))
+                .await
+                .unwrap();
+            drop(server);
+
+            let content = fs::read_to_string(&path).unwrap();
+            assert!(!content.contains(token.expose()));
+            assert!(!content.contains(MOCK_SECRET_KEY));
+            assert!(content.contains(REDACTED));
+
+            // the lender is gone, responses come from the cassette
+            let replay = |mode| {
+                crate::api::client::LoanClient::new(
+                    base_url.clone(),
+                    MOCK_SECRET_KEY.to_string(),
+                    MOCK_PUBLIC_KEY.to_string(),
+                    "mock lender".to_string(),
+                    "logo_url".to_string(),
+                    "http://127.0.0.1:8020/".to_string(),
+                )
+                .with_transport(
+                    Transport::default().with_cassette(Cassette::replay(&path, mode).unwrap()),
+                )
+            };
+
+            let strict = replay(MatchMode::Strict);
+            let token = strict.get_auth_token().await.unwrap().access_token;
+            assert_eq!(token, REDACTED);
+            let replayed = strict
+                .get_anchors(token.expose(), 1, pagination(1))
+                .await
+                .unwrap();
+            assert_eq!(replayed.data[0].anchor_id, first.data[0].anchor_id);
+            let err = strict
+                .get_anchors(token.expose(), 1, pagination(5))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, SupremoError::Config(_)));
+
+            let lenient = replay(MatchMode::Lenient);
+            let page = lenient
+                .get_anchors("token", 1, pagination(2))
+                .await
+                .unwrap();
+            assert_eq!(page.data[0].anchor_id, 4);
+            assert!(lenient.get_anchors("token", 1, pagination(9)).await.is_ok());
+
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn replayed_errors_keep_their_code() {
+            let path = cassette_path("error");
+            let lender = FakeLender::new().with_client(1, 10_000.0);
+            lender.fail_next(Endpoint::ClientLimit, Failure::InsufficientLimit);
+            let server = MockServer::start(lender).unwrap();
+            let recording = server
+                .client()
+                .with_transport(Transport::default().with_cassette(Cassette::record(&path)));
+            let err = recording.client_limit("token", 1).await.unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InsufficientLimit));
+            drop(server);
+
+            let replay = recording.with_transport(
+                Transport::default()
+                    .with_cassette(Cassette::replay(&path, MatchMode::Strict).unwrap()),
+            );
+            let err = replay.client_limit("token", 1).await.unwrap_err();
+            assert_eq!(err.status(), Some(StatusCode::BAD_REQUEST));
+            assert_eq!(err.kind(), Some(ApiErrorKind::InsufficientLimit));
+
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -1,11 +1,20 @@
-use std::io::Error;
+use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 
+use super::credentials::{CredentialStore, Credentials};
+use super::error::{ApiError, ApiErrorKind, Result, SupremoError};
+use super::idempotency::{Begin, IdempotencyKey, IdempotencyStore, IDEMPOTENCY_KEY_HEADER};
+use super::oauth::{random_token, AuthorizationRequest, PkceChallenge};
+use super::retry::{Endpoint, RetryPolicy};
+use super::secrets::SecretString;
+use super::token::TokenManager;
+use super::transport::{RawResponse, Transport};
 use super::types::{
-    AnchorPagination, CalculateLonaResponse, ClientLimit, LoanCreted, LoanInput, OuathCode,
-    OuathToken, OuathUser, PaginatedAnchors,
+    AccountChange, AnchorPagination, CalculateLonaResponse, ClientLimit, LinkedAccount, LoanCreted,
+    LoanInput, OuathCode, OuathToken, OuathUser, PaginatedAnchors, TokenIntrospection,
+    TokenTypeHint, UserSession, UserToken,
 };
 
 /// # Examples
@@ -30,8 +39,8 @@ use super::types::{
 ///     );
 ///     }
 /// ```
-
-/// // Examples
+///
+/// # Examples
 /// ```
 ///     use supremo_loan::api::client::LoanClient;
 ///     #[tokio::main]
@@ -52,9 +61,9 @@ use super::types::{
 ///             r#"{"base_url":"base_url","public_key":"public_key","name":"bank_name","logo_url":"logo_url","redirect_url":"redirect_url"}"#
 ///         );
 ///     }
-///```
-
-///    // Examples
+/// ```
+///
+/// # Examples
 /// ```
 ///     use supremo_loan::api::client::LoanClient;
 ///    #[tokio::main]
@@ -79,8 +88,8 @@ use super::types::{
 ///         }
 ///     }
 /// ```
-
-///  // Examples
+///
+/// # Examples
 /// ```
 ///     use supremo_loan::api::client::LoanClient;
 ///     #[tokio::main]
@@ -105,8 +114,8 @@ use super::types::{
 ///         }
 ///     }
 /// ```
-
-/// // Examples
+///
+/// # Examples
 ///
 /// ```
 ///     use supremo_loan::api::client::LoanClient;
@@ -132,7 +141,7 @@ use super::types::{
 ///         }
 ///     }
 /// ```
-/// // Examples
+/// # Examples
 ///
 /// ```
 ///     use supremo_loan::api::client::LoanClient;
@@ -152,22 +161,35 @@ use super::types::{
 ///                 println!("ouath {:?}", ouath);
 ///             }
 ///             Err(e) => {
-///                 println!("error", e.to_string());
+///                 println!("error {:?}", e.to_string());
 ///             }
 ///         }
 ///     }
-/// }
 /// ```
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LoanClient {
     pub base_url: String,
     #[serde(skip_serializing)]
-    secret_key: String,
+    secret_key: SecretString,
     pub public_key: String,
     pub name: String,
-    pub logo_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo_url: Option<String>,
     pub redirect_url: String,
+    #[serde(skip)]
+    transport: Transport,
+    #[serde(skip)]
+    retry_policy: RetryPolicy,
+    #[serde(skip)]
+    idempotency_store: IdempotencyStore,
+    #[serde(skip)]
+    token_manager: Option<TokenManager>,
+    #[serde(skip)]
+    credential_store: Option<CredentialStore>,
+    #[serde(skip)]
+    pkce: bool,
+    #[serde(skip)]
+    public_client: bool,
 }
 
 impl LoanClient {
@@ -181,222 +203,446 @@ impl LoanClient {
     ) -> Self {
         Self {
             base_url,
-            secret_key,
+            secret_key: secret_key.into(),
             public_key,
             name,
-            logo_url,
+            logo_url: Some(logo_url),
             redirect_url,
+            transport: Transport::default(),
+            retry_policy: RetryPolicy::default(),
+            idempotency_store: IdempotencyStore::default(),
+            token_manager: None,
+            credential_store: None,
+            pkce: false,
+            public_client: false,
         }
     }
 
-    pub async fn get_auth_token(&self) -> Result<OuathToken, Error> {
-        let url = format!("{}/api/v1/oauth/auth/token/", self.base_url);
+    /// build a client whose urls and keys are validated up front
+    pub fn builder() -> LoanClientBuilder {
+        LoanClientBuilder::default()
+    }
 
-        let client = reqwest::Client::new();
+    /// share a configured transport (timeouts, pool, proxy, certificates) with this client
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
 
-        let body = json!({
-            "grant_type": "client_credentials",
-            "redirect_uri" : self.redirect_url,
-            "cliend_id" : self.public_key,
-        });
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
 
-        let mut headers = reqwest::header::HeaderMap::new();
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
 
-        let res = client
-            .post(&url)
-            .basic_auth(&self.public_key, Some(&self.secret_key))
-            .headers(headers)
-            .form(&body)
-            .send()
-            .await;
+    /// share the record of loan applications between independently built clients
+    pub fn with_idempotency_store(mut self, idempotency_store: IdempotencyStore) -> Self {
+        self.idempotency_store = idempotency_store;
+        self
+    }
 
-        match res {
-            Ok(res) => {
-                // march status code
-                match res.status() {
-                    reqwest::StatusCode::OK => {
-                        let json = res.json::<OuathToken>().await;
-                        match json {
-                            Ok(json) => Ok(json),
-                            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                        }
-                    }
-                    _ => {
-                        let json = res.json::<serde_json::Value>().await;
-                        match json {
-                            Ok(json) => Err(Error::new(
-                                std::io::ErrorKind::Other,
-                                serde_json::to_string(&json).unwrap(),
-                            )),
-                            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                        }
-                    }
-                }
+    pub fn idempotency_store(&self) -> &IdempotencyStore {
+        &self.idempotency_store
+    }
+
+    /// let the client fetch, cache and refresh its own client-credentials token,
+    /// methods then accept `None` as bearer token
+    pub fn with_token_manager(mut self, token_manager: TokenManager) -> Self {
+        self.token_manager = Some(token_manager);
+        self
+    }
+
+    /// authenticate with the rotatable credentials of `credential_store` instead of the
+    /// keys the client was built with, clones share the store
+    ///
+    /// `public_key` keeps the value it had when the store was attached
+    pub fn with_credential_store(mut self, credential_store: CredentialStore) -> Self {
+        self.public_key = credential_store.current().public_key;
+        self.credential_store = Some(credential_store);
+        self
+    }
+
+    pub fn credential_store(&self) -> Option<&CredentialStore> {
+        self.credential_store.as_ref()
+    }
+
+    /// the managed client-credentials token, fetched or refreshed when needed
+    pub async fn bearer_token(&self) -> Result<SecretString> {
+        let token_manager = self.token_manager.as_ref().ok_or_else(|| {
+            SupremoError::Config(
+                "no bearer token given and token management is disabled".to_string(),
+            )
+        })?;
+        token_manager.token(|| self.get_auth_token()).await
+    }
+
+    pub async fn get_auth_token(&self) -> Result<OuathToken> {
+        if self.public_client {
+            return Err(SupremoError::Config(
+                "the client_credentials grant needs a secret_key, which public clients do not have"
+                    .to_string(),
+            ));
+        }
+
+        self.execute_oauth(Endpoint::AuthToken, "token", |credentials| {
+            json!({
+                "grant_type": "client_credentials",
+                "redirect_uri" : self.redirect_url,
+                "cliend_id" : credentials.public_key,
+            })
+        })
+        .await
+    }
+
+    /// revoke an access or refresh token (RFC 7009), e.g. when a borrower unlinks their account
+    ///
+    /// revoking a token that is unknown or already revoked succeeds
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use supremo_loan::api::client::LoanClient;
+    /// use supremo_loan::api::types::{TokenTypeHint, UserSession};
+    /// # async fn run(client: LoanClient, session: UserSession) {
+    /// client
+    ///     .revoke_token(session.token.refresh_token.expose(), Some(TokenTypeHint::RefreshToken))
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn revoke_token(&self, token: &str, hint: Option<TokenTypeHint>) -> Result<()> {
+        let mut body = json!({ "token": token });
+        if let Some(hint) = hint {
+            body["token_type_hint"] = json!(hint.as_str());
+        }
+
+        self.execute_oauth::<serde::de::IgnoredAny>(Endpoint::RevokeToken, "revoke", |_| {
+            body.clone()
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// ask the lender whether a token is active and who it was issued to (RFC 7662)
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use supremo_loan::api::client::LoanClient;
+    /// # async fn run(client: LoanClient, token: &str) {
+    /// let introspection = client.introspect_token(token, None).await.unwrap();
+    /// if !introspection.is_valid() {
+    ///     // reject the token
+    /// }
+    /// # }
+    /// ```
+    pub async fn introspect_token(
+        &self,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<TokenIntrospection> {
+        let mut body = json!({ "token": token });
+        if let Some(hint) = hint {
+            body["token_type_hint"] = json!(hint.as_str());
+        }
+
+        self.execute_oauth(Endpoint::IntrospectToken, "introspect", |_| body.clone())
+            .await
+    }
+
+    /// form request to `/api/v1/oauth/auth/{endpoint}/`, authenticated with basic auth
+    /// or, for public clients, identified by `client_id` only
+    fn oauth_request(
+        &self,
+        credentials: &Credentials,
+        endpoint: &str,
+        mut body: serde_json::Value,
+    ) -> reqwest::RequestBuilder {
+        let url = format!("{}/api/v1/oauth/auth/{}/", self.base_url, endpoint);
+        let request = self.transport.client().post(&url);
+
+        // use x-www-form-urlencoded
+        if self.public_client {
+            body["client_id"] = json!(credentials.public_key);
+            request.form(&body)
+        } else {
+            request
+                .basic_auth(
+                    &credentials.public_key,
+                    Some(credentials.secret_key.expose()),
+                )
+                .form(&body)
+        }
+    }
+
+    fn credentials(&self) -> Credentials {
+        match &self.credential_store {
+            Some(credential_store) => credential_store.current(),
+            None => Credentials::new(self.public_key.clone(), self.secret_key.clone()),
+        }
+    }
+
+    /// send an oauth request; credentials rejected by the lender are re-read from the
+    /// credential store's provider and, during the grace period, the previous ones are tried
+    ///
+    /// when every attempt is rejected the lender's error is returned, a failed re-read is
+    /// left in [`CredentialStore::reload_error`]
+    ///
+    /// codes and refresh tokens are single use, so those requests are only sent again when
+    /// the lender rejected the client itself (`invalid_client`)
+    async fn execute_oauth<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        path: &str,
+        body: impl Fn(&Credentials) -> serde_json::Value,
+    ) -> Result<T> {
+        let credentials = self.credentials();
+        let request = self.oauth_request(&credentials, path, body(&credentials));
+        let err = match self.execute(endpoint, request).await {
+            Err(e) if credentials_rejected(endpoint, &e) => e,
+            result => return result,
+        };
+        let Some(credential_store) = &self.credential_store else {
+            return Err(err);
+        };
+
+        let mut fallbacks = Vec::new();
+        // without the provider's keys, the previous ones are still worth a try
+        if let Ok(true) = credential_store.reload() {
+            fallbacks.push(credential_store.current());
+        }
+        fallbacks.extend(credential_store.previous());
+        for fallback in fallbacks.into_iter().filter(|c| *c != credentials) {
+            let request = self.oauth_request(&fallback, path, body(&fallback));
+            match self.execute(endpoint, request).await {
+                Err(e) if credentials_rejected(endpoint, &e) => continue,
+                result => return result,
             }
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
         }
+        Err(err)
     }
 
-    async fn get_aouth_user(&self, bearer_token: String) -> Result<OuathUser, Error> {
+    /// profile of the borrower the user access token was issued to
+    pub async fn get_user_info(&self, bearer_token: &str) -> Result<OuathUser> {
         let url = format!("{}/api/v1/oauth/auth/user", self.base_url);
-        let client = reqwest::Client::new();
-
-        let mut headers = reqwest::header::HeaderMap::new();
+        let client = self.transport.client();
 
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", &bearer_token).parse().unwrap(),
-        );
+        let request = client.get(&url).bearer_auth(bearer_token);
 
-        let res = client.get(&url).headers(headers).send().await;
+        self.execute(Endpoint::UserInfo, request).await
+    }
 
-        match res {
-            Ok(res) => match res.status() {
-                reqwest::StatusCode::OK => {
-                    let json = res.json::<OuathUser>().await;
-                    match json {
-                        Ok(json) => Ok(json),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-                _ => {
-                    let json = res.json::<serde_json::Value>().await;
-                    match json {
-                        Ok(json) => Err(Error::new(
-                            std::io::ErrorKind::Other,
-                            serde_json::to_string(&json).unwrap(),
-                        )),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-            },
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
+    /// url of the lender's authorize endpoint to send the borrower to for linking their account
+    ///
+    /// the returned request holds a random `state`, and the PKCE verifier when PKCE is
+    /// enabled, keep it until the borrower is redirected back and pass it to
+    /// [`LoanClient::exchange_authorization_callback`]
+    ///
+    /// # Examples
+    /// ```
+    /// use supremo_loan::api::client::LoanClient;
+    ///
+    /// let client = LoanClient::builder()
+    ///     .base_url("https://lender.example.com")
+    ///     .public_key("public_key")
+    ///     .secret_key("secret_key")
+    ///     .name("bank name")
+    ///     .redirect_url("https://app.example.com/callback")
+    ///     .build()
+    ///     .unwrap();
+    /// let request = client.authorization_url(Some("read")).unwrap();
+    /// assert!(request.url.starts_with(
+    ///     "https://lender.example.com/api/v1/oauth/auth/authorize?response_type=code&client_id=public_key"
+    /// ));
+    /// assert!(request.url.contains(&format!("state={}", request.state)));
+    /// ```
+    pub fn authorization_url(&self, scope: Option<&str>) -> Result<AuthorizationRequest> {
+        let url = format!("{}/api/v1/oauth/auth/authorize", self.base_url);
+        let mut url = reqwest::Url::parse(&url)
+            .map_err(|e| SupremoError::Config(format!("invalid base_url: {}", e)))?;
+        let state = random_token();
+        let pkce = self.pkce.then(PkceChallenge::generate);
+
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &self.credentials().public_key)
+                .append_pair("redirect_uri", &self.redirect_url);
+            if let Some(scope) = scope {
+                query.append_pair("scope", scope);
+            }
+            query.append_pair("state", &state);
+            if let Some(pkce) = &pkce {
+                query
+                    .append_pair("code_challenge", &pkce.challenge)
+                    .append_pair("code_challenge_method", PkceChallenge::METHOD);
+            }
         }
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state,
+            code_verifier: pkce.map(|pkce| pkce.verifier),
+        })
     }
 
-    pub async fn exchange_code_auth(&self, code: &str) -> Result<OuathUser, Error> {
-        let url = format!("{}/api/v1/oauth/auth/token/", self.base_url);
-        let client = reqwest::Client::new();
+    /// verify the `state` of the authorization callback and exchange its `code`
+    ///
+    /// callbacks with a missing or mismatched state are rejected with
+    /// [`SupremoError::InvalidState`] before the code is sent to the lender, the PKCE
+    /// verifier of the request is sent along with the code
+    pub async fn exchange_authorization_callback(
+        &self,
+        request: &AuthorizationRequest,
+        code: &str,
+        state: Option<&str>,
+    ) -> Result<UserSession> {
+        request.verify_state(state)?;
+        self.exchange_code(
+            code,
+            request.code_verifier.as_ref().map(SecretString::expose),
+        )
+        .await
+    }
 
-        let body = json!({
+    /// exchange the code obtained by the borrower for their account and tokens
+    pub async fn exchange_code_auth(&self, code: &str) -> Result<UserSession> {
+        self.exchange_code(code, None).await
+    }
+
+    async fn exchange_code(&self, code: &str, code_verifier: Option<&str>) -> Result<UserSession> {
+        let mut body = json!({
             "code": code,
             "grant_type": "authorization_code",
             "redirect_uri": self.redirect_url,
         });
-        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(code_verifier) = code_verifier {
+            body["code_verifier"] = json!(code_verifier);
+        }
 
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
+        let code: OuathCode = self
+            .execute_oauth(Endpoint::ExchangeCode, "token", |_| body.clone())
+            .await?;
+        let token = UserToken::from_code(code);
+        let user = self.get_user_info(token.access_token.expose()).await?;
+        Ok(UserSession { user, token })
+    }
 
-        // use x-www-form-urlencoded
-        let res = client
-            .post(&url)
-            .basic_auth(&self.public_key, Some(&self.secret_key))
-            .headers(headers)
-            .form(&body)
-            .send()
-            .await;
+    /// exchange the code and bundle the borrower with their tokens and this lender's name
+    pub async fn link_account(&self, code: &str) -> Result<LinkedAccount> {
+        let session = self.exchange_code_auth(code).await?;
+        Ok(LinkedAccount::new(&self.name, session))
+    }
 
-        match res {
-            Ok(res) => {
-                // march status code
-                match res.status() {
-                    reqwest::StatusCode::OK => {
-                        let json = res.json::<OuathCode>().await;
-                        match json {
-                            Ok(json) => {
-                                let user = self.get_aouth_user(json.access_token).await;
-                                match user {
-                                    Ok(user) => Ok(user),
-                                    Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                                }
-                            }
-                            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                        }
-                    }
-                    _ => {
-                        let json = res.json::<serde_json::Value>().await;
-                        match json {
-                            Ok(json) => Err(Error::new(
-                                std::io::ErrorKind::Other,
-                                serde_json::to_string(&json).unwrap(),
-                            )),
-                            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                        }
-                    }
-                }
-            }
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
+    /// re-fetch the borrower's profile of a stored account, refreshing its token when
+    /// it is about to expire
+    ///
+    /// `account` is updated in place, the returned list tells what changed in the
+    /// profile (e.g. a new `anchor_id`) so the application can react to it
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use supremo_loan::api::client::LoanClient;
+    /// use supremo_loan::api::types::{AccountChange, LinkedAccount};
+    /// # async fn run(client: LoanClient, mut account: LinkedAccount) {
+    /// let changes = client.revalidate_account(&mut account).await.unwrap();
+    /// for change in changes {
+    ///     if let AccountChange::AnchorId { old, new } = change {
+    ///         println!("anchor changed from {} to {}", old, new);
+    ///     }
+    /// }
+    /// // persist `account` again, its token may have been rotated
+    /// # }
+    /// ```
+    pub async fn revalidate_account(
+        &self,
+        account: &mut LinkedAccount,
+    ) -> Result<Vec<AccountChange>> {
+        if account.token.is_expired(Duration::from_secs(60)) {
+            account.token = self.refresh_user_token(&account.token).await?;
         }
+        let user = self
+            .get_user_info(account.token.access_token.expose())
+            .await?;
+        let changes = account.changes(&user);
+        account.user = user;
+        Ok(changes)
     }
 
-    pub async fn client_limit(
+    /// get a new access token for a borrower with the `refresh_token` grant
+    ///
+    /// the returned token carries the rotated refresh token when the lender issues a
+    /// new one, otherwise the current refresh token is kept
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use supremo_loan::api::client::LoanClient;
+    /// use supremo_loan::api::types::UserSession;
+    /// # async fn run(client: LoanClient, mut session: UserSession) {
+    /// if session.token.is_expired(Duration::from_secs(60)) {
+    ///     session.token = client.refresh_user_token(&session.token).await.unwrap();
+    /// }
+    /// # }
+    /// ```
+    pub async fn refresh_user_token(&self, token: &UserToken) -> Result<UserToken> {
+        let body = json!({
+            "grant_type": "refresh_token",
+            "refresh_token": token.refresh_token.expose(),
+            "redirect_uri": self.redirect_url,
+        });
+
+        let code: OuathCode = self
+            .execute_oauth(Endpoint::RefreshToken, "token", |_| body.clone())
+            .await?;
+        let mut refreshed = UserToken::from_code(code);
+        if refreshed.refresh_token.is_empty() {
+            refreshed.refresh_token = token.refresh_token.clone();
+        }
+        Ok(refreshed)
+    }
+
+    /// `bearer_token` may be `None` when the client manages its own token
+    pub async fn client_limit<'a>(
         &self,
-        bearer_token: &str,
+        bearer_token: impl Into<Option<&'a str>>,
         client_id: i32,
-    ) -> Result<ClientLimit, Error> {
+    ) -> Result<ClientLimit> {
         let url = format!("{}/api/v1/oauth/client-limit/{}", self.base_url, client_id);
 
-        let client = reqwest::Client::new();
-
-        let mut headers = reqwest::header::HeaderMap::new();
-
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", &bearer_token).parse().unwrap(),
-        );
-
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
-
-        let res = client.get(&url).headers(headers).send().await;
+        let client = self.transport.client();
 
-        match res {
-            Ok(res) => {
-                let json = res.json::<ClientLimit>().await;
-                match json {
-                    Ok(json) => Ok(json),
-                    Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                }
-            }
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-        }
+        self.execute_authorized(Endpoint::ClientLimit, bearer_token.into(), false, |token| {
+            client
+                .get(&url)
+                .bearer_auth(token)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+        })
+        .await
     }
 
-    pub async fn get_anchors(
+    /// `bearer_token` may be `None` when the client manages its own token
+    pub async fn get_anchors<'a>(
         &self,
-        bearer_token: &str,
+        bearer_token: impl Into<Option<&'a str>>,
         client_id: i32,
         pagination: Option<AnchorPagination>,
-    ) -> Result<PaginatedAnchors, Error> {
+    ) -> Result<PaginatedAnchors> {
         let url = format!(
             "{}/api/v1/oauth/client-anchors/{}",
             self.base_url, client_id
         );
-        let client = reqwest::Client::new();
-
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", &bearer_token).parse().unwrap(),
-        );
-
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
+        let client = self.transport.client();
 
         // make pagination query string in reqwest
-        let mut url = reqwest::Url::parse(&url).unwrap();
+        let mut url = reqwest::Url::parse(&url)
+            .map_err(|e| SupremoError::Config(format!("invalid base_url: {}", e)))?;
         if let Some(pagination) = pagination {
             if let Some(page) = pagination.page {
                 url.query_pairs_mut().append_pair("page", &page.to_string());
@@ -410,157 +656,420 @@ impl LoanClient {
             }
         }
 
-        let res = client.get(url).headers(headers).send().await;
-
-        match res {
-            Ok(res) => match res.status() {
-                reqwest::StatusCode::OK => {
-                    let json = res.json::<PaginatedAnchors>().await;
-                    match json {
-                        Ok(json) => Ok(json),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-                _ => {
-                    let json = res.json::<serde_json::Value>().await;
-                    match json {
-                        Ok(json) => Err(Error::new(
-                            std::io::ErrorKind::Other,
-                            serde_json::to_string(&json).unwrap(),
-                        )),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-            },
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-        }
+        self.execute_authorized(Endpoint::Anchors, bearer_token.into(), false, |token| {
+            client
+                .get(url.clone())
+                .bearer_auth(token)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+        })
+        .await
     }
 
+    /// `bearer_token` may be `None` when the client manages its own token
     pub async fn calculate_loan(
         &self,
-        bearer_token: String,
+        bearer_token: impl Into<Option<String>>,
         body: Vec<LoanInput>,
-    ) -> Result<Vec<CalculateLonaResponse>, Error> {
+    ) -> Result<Vec<CalculateLonaResponse>> {
         let url = format!("{}/api/v1/oauth/calc-loan", self.base_url);
 
-        // make sure body.len() > 0
-        if body.len() == 0 {
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                "provide at least one input value",
-            ));
-        }
-        let client_id = body[0].client_id;
-        for i in 1..body.len() {
-            if body[i].client_id != client_id {
-                return Err(Error::new(
-                    std::io::ErrorKind::Other,
-                    "all client_id must be the same",
-                ));
-            }
-        }
+        validate_loan_inputs(&body)?;
 
-        let client = reqwest::Client::new();
+        let client = self.transport.client();
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", &bearer_token).parse().unwrap(),
-        );
+        let bearer_token = bearer_token.into();
+        self.execute_authorized(
+            Endpoint::CalculateLoan,
+            bearer_token.as_deref(),
+            false,
+            |token| client.post(&url).bearer_auth(token).json(&body),
+        )
+        .await
+    }
 
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
+    /// `bearer_token` may be `None` when the client manages its own token
+    pub async fn apply_for_loan(
+        &self,
+        bearer_token: impl Into<Option<String>>,
+        body: Vec<LoanInput>,
+    ) -> Result<LoanCreted> {
+        let url = format!("{}/api/v1/oauth/apply-loan", self.base_url);
 
-        let res = client.post(&url).headers(headers).json(&body).send().await;
+        validate_loan_inputs(&body)?;
 
-        match res {
-            Ok(res) => match res.status() {
-                reqwest::StatusCode::OK => {
-                    let json = res.json::<Vec<CalculateLonaResponse>>().await;
-                    match json {
-                        Ok(json) => Ok(json),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-                _ => {
-                    let json = res.json::<serde_json::Value>().await;
-                    match json {
-                        Ok(json) => Err(Error::new(
-                            std::io::ErrorKind::Other,
-                            serde_json::to_string(&json).unwrap(),
-                        )),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-            },
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-        }
+        let client = self.transport.client();
+
+        let bearer_token = bearer_token.into();
+        self.execute_authorized(
+            Endpoint::ApplyLoan,
+            bearer_token.as_deref(),
+            false,
+            |token| client.post(&url).bearer_auth(token).json(&body),
+        )
+        .await
     }
 
-    pub async fn apply_for_loan(
+    /// apply for a loan sending `idempotency_key` in the `Idempotency-Key` header
+    ///
+    /// the request is retried on transient failures, and an application whose key
+    /// already completed returns the stored result without being sent again; one whose
+    /// outcome is unknown (timed out or cancelled) is sent again with the same key
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use supremo_loan::api::client::LoanClient;
+    /// use supremo_loan::api::idempotency::IdempotencyKey;
+    /// use supremo_loan::api::types::LoanInput;
+    /// # async fn run(client: LoanClient, loan_input: Vec<LoanInput>) {
+    /// let key = IdempotencyKey::for_batch(&loan_input);
+    /// let created = client
+    ///     .apply_for_loan_with_key("token".to_string(), loan_input, &key)
+    ///     .await;
+    /// # }
+    /// ```
+    pub async fn apply_for_loan_with_key(
         &self,
-        bearer_token: String,
+        bearer_token: impl Into<Option<String>>,
         body: Vec<LoanInput>,
-    ) -> Result<LoanCreted, Error> {
+        idempotency_key: &IdempotencyKey,
+    ) -> Result<LoanCreted> {
         let url = format!("{}/api/v1/oauth/apply-loan", self.base_url);
 
-        // make sure body.len() > 0
-        if body.len() == 0 {
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                "provide at least one input value",
-            ));
+        validate_loan_inputs(&body)?;
+
+        let pending = match self.idempotency_store.begin(idempotency_key)? {
+            Begin::Completed(created) => return Ok(created),
+            Begin::Started(pending) => pending,
+        };
+
+        let client = self.transport.client();
+
+        let bearer_token = bearer_token.into();
+        let result = self
+            .execute_authorized::<LoanCreted>(
+                Endpoint::ApplyLoan,
+                bearer_token.as_deref(),
+                true,
+                |token| {
+                    client
+                        .post(&url)
+                        .bearer_auth(token)
+                        .header(IDEMPOTENCY_KEY_HEADER, idempotency_key.as_str())
+                        .json(&body)
+                },
+            )
+            .await;
+        match &result {
+            Ok(created) => pending.complete(created),
+            Err(e) => pending.fail(e),
+        }
+        result
+    }
+
+    /// send a request authorized with `bearer_token`, or with the managed token when it
+    /// is `None`; a managed token rejected by the lender is refreshed once
+    async fn execute_authorized<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        bearer_token: Option<&str>,
+        has_idempotency_key: bool,
+        request: impl Fn(&str) -> reqwest::RequestBuilder,
+    ) -> Result<T> {
+        if let Some(token) = bearer_token {
+            return self
+                .execute_with_key(endpoint, request(token), has_idempotency_key)
+                .await;
         }
 
-        let client_id = body[0].client_id;
-        for i in 1..body.len() {
-            if body[i].client_id != client_id {
-                return Err(Error::new(
-                    std::io::ErrorKind::Other,
-                    "all client_id must be the same",
-                ));
+        let token = self.bearer_token().await?;
+        match self
+            .execute_with_key(endpoint, request(token.expose()), has_idempotency_key)
+            .await
+        {
+            Err(e) if e.is_auth_expired() => {
+                if let Some(token_manager) = &self.token_manager {
+                    token_manager.invalidate(token.expose()).await;
+                }
+                let token = self.bearer_token().await?;
+                self.execute_with_key(endpoint, request(token.expose()), has_idempotency_key)
+                    .await
             }
+            result => result,
         }
+    }
 
-        let client = reqwest::Client::new();
+    /// send the request, retrying transient failures as allowed by the retry policy
+    async fn execute<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T> {
+        self.execute_with_key(endpoint, request, false).await
+    }
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", &bearer_token).parse().unwrap(),
-        );
+    async fn execute_with_key<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        request: reqwest::RequestBuilder,
+        has_idempotency_key: bool,
+    ) -> Result<T> {
+        if !self.retry_policy.retries(endpoint, has_idempotency_key) {
+            return send(&self.transport, request).await;
+        }
 
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
+        let mut attempt = 1;
+        loop {
+            // json and form bodies are buffered, so the request can always be cloned
+            let attempt_request = request.try_clone().ok_or_else(|| {
+                SupremoError::Config("request body cannot be sent more than once".to_string())
+            })?;
+            match send(&self.transport, attempt_request).await {
+                Err(e) => match self.retry_policy.delay_for(attempt, &e) {
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                result => return result,
+            }
+        }
+    }
+}
 
-        let res = client.post(&url).headers(headers).json(&body).send().await;
+/// Builder for [`LoanClient`] that rejects invalid configuration instead of producing
+/// a client that fails on its first request.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::client::LoanClient;
+///
+/// let client = LoanClient::builder()
+///     .base_url("https://lender.example.com/")
+///     .public_key("public_key")
+///     .secret_key("secret_key")
+///     .name("bank name")
+///     .redirect_url("https://app.example.com/callback")
+///     .build()
+///     .unwrap();
+/// assert_eq!(client.base_url, "https://lender.example.com");
+/// assert!(client.logo_url.is_none());
+///
+/// let err = LoanClient::builder()
+///     .base_url("base_url")
+///     .public_key("public_key")
+///     .secret_key("secret_key")
+///     .name("bank name")
+///     .redirect_url("https://app.example.com/callback")
+///     .build();
+/// assert!(err.is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct LoanClientBuilder {
+    base_url: Option<String>,
+    secret_key: Option<SecretString>,
+    public_key: Option<String>,
+    name: Option<String>,
+    logo_url: Option<String>,
+    redirect_url: Option<String>,
+    transport: Option<Transport>,
+    retry_policy: Option<RetryPolicy>,
+    idempotency_store: Option<IdempotencyStore>,
+    token_manager: Option<TokenManager>,
+    credential_store: Option<CredentialStore>,
+    pkce: bool,
+    public_client: bool,
+}
 
-        match res {
-            Ok(res) => match res.status() {
-                reqwest::StatusCode::OK => {
-                    let json = res.json::<LoanCreted>().await;
-                    match json {
-                        Ok(json) => Ok(json),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-                _ => {
-                    let json = res.json::<serde_json::Value>().await;
-                    match json {
-                        Ok(json) => Err(Error::new(
-                            std::io::ErrorKind::Other,
-                            serde_json::to_string(&json).unwrap(),
-                        )),
-                        Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
-                    }
-                }
-            },
-            Err(e) => Err(Error::new(std::io::ErrorKind::Other, e)),
+impl LoanClientBuilder {
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    pub fn secret_key(mut self, secret_key: impl Into<SecretString>) -> Self {
+        self.secret_key = Some(secret_key.into());
+        self
+    }
+
+    pub fn public_key(mut self, public_key: impl Into<String>) -> Self {
+        self.public_key = Some(public_key.into());
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn logo_url(mut self, logo_url: impl Into<String>) -> Self {
+        self.logo_url = Some(logo_url.into());
+        self
+    }
+
+    pub fn redirect_url(mut self, redirect_url: impl Into<String>) -> Self {
+        self.redirect_url = Some(redirect_url.into());
+        self
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    pub fn idempotency_store(mut self, idempotency_store: IdempotencyStore) -> Self {
+        self.idempotency_store = Some(idempotency_store);
+        self
+    }
+
+    /// rotatable credentials, `public_key` and `secret_key` default to its current ones
+    pub fn credential_store(mut self, credential_store: CredentialStore) -> Self {
+        self.credential_store = Some(credential_store);
+        self
+    }
+
+    /// add a PKCE challenge to authorize urls and send its verifier with the code exchange
+    pub fn pkce(mut self, pkce: bool) -> Self {
+        self.pkce = pkce;
+        self
+    }
+
+    /// client that cannot keep a secret (mobile apps, SPAs): no `secret_key` is
+    /// required or sent, token requests carry `client_id` and PKCE is enabled
+    pub fn public_client(mut self) -> Self {
+        self.public_client = true;
+        self.pkce = true;
+        self
+    }
+
+    /// cache the client-credentials token and refresh it `refresh_before` its expiry
+    pub fn manage_tokens(mut self, refresh_before: Duration) -> Self {
+        self.token_manager = Some(TokenManager::new(refresh_before));
+        self
+    }
+
+    pub fn build(self) -> Result<LoanClient> {
+        let base_url = parse_url("base_url", self.base_url)?;
+        let redirect_url = parse_url("redirect_url", self.redirect_url)?;
+        let stored = self.credential_store.as_ref().map(CredentialStore::current);
+        let public_key = self
+            .public_key
+            .or_else(|| stored.as_ref().map(|stored| stored.public_key.clone()));
+        let public_key = required("public_key", public_key)?;
+        let secret_key = self.secret_key.or(stored.map(|stored| stored.secret_key));
+        let secret_key = if self.public_client {
+            secret_key.unwrap_or_default()
+        } else {
+            required_secret("secret_key", secret_key)?
+        };
+        let name = required("name", self.name)?;
+        if let Some(retry_policy) = &self.retry_policy {
+            retry_policy.validate()?;
         }
+
+        Ok(LoanClient {
+            // endpoints are appended as `{base_url}/api/...`
+            base_url: base_url.as_str().trim_end_matches('/').to_string(),
+            secret_key,
+            public_key,
+            name,
+            logo_url: self.logo_url.filter(|logo_url| !logo_url.trim().is_empty()),
+            redirect_url: redirect_url.to_string(),
+            transport: self.transport.unwrap_or_default(),
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            idempotency_store: self.idempotency_store.unwrap_or_default(),
+            token_manager: self.token_manager,
+            credential_store: self.credential_store,
+            pkce: self.pkce,
+            public_client: self.public_client,
+        })
+    }
+}
+
+/// whether other credentials may succeed where `error` failed
+fn credentials_rejected(endpoint: Endpoint, error: &SupremoError) -> bool {
+    match endpoint {
+        Endpoint::AuthToken => {
+            error.is_auth_expired() || error.kind() == Some(ApiErrorKind::InvalidClient)
+        }
+        _ => error.kind() == Some(ApiErrorKind::InvalidClient),
+    }
+}
+
+pub(crate) fn required(field: &str, value: Option<String>) -> Result<String> {
+    match value {
+        Some(value) if !value.trim().is_empty() => Ok(value),
+        Some(_) => Err(SupremoError::Config(format!("{} must not be empty", field))),
+        None => Err(SupremoError::Config(format!("{} is required", field))),
+    }
+}
+
+fn required_secret(field: &str, value: Option<SecretString>) -> Result<SecretString> {
+    match value {
+        Some(value) if !value.expose().trim().is_empty() => Ok(value),
+        Some(_) => Err(SupremoError::Config(format!("{} must not be empty", field))),
+        None => Err(SupremoError::Config(format!("{} is required", field))),
+    }
+}
+
+pub(crate) fn parse_url(field: &str, value: Option<String>) -> Result<reqwest::Url> {
+    let value = required(field, value)?;
+    let url = reqwest::Url::parse(value.trim()).map_err(|e| {
+        SupremoError::Config(format!("{} {:?} is not a valid url: {}", field, value, e))
+    })?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err(SupremoError::Config(format!(
+            "{} {:?} must be an http(s) url",
+            field, value
+        )));
+    }
+    Ok(url)
+}
+
+/// all loan inputs in a batch must belong to the same client
+pub(crate) fn validate_loan_inputs(body: &[LoanInput]) -> Result<()> {
+    // make sure body.len() > 0
+    let first = body
+        .first()
+        .ok_or_else(|| SupremoError::Validation("provide at least one input value".to_string()))?;
+    if body.iter().any(|input| input.client_id != first.client_id) {
+        return Err(SupremoError::Validation(
+            "all client_id must be the same".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// send the request and decode a successful response into `T`, keeping the status
+/// code and payload of any other response in the returned error
+async fn send<T: DeserializeOwned>(
+    transport: &Transport,
+    request: reqwest::RequestBuilder,
+) -> Result<T> {
+    let RawResponse {
+        status,
+        headers,
+        body: bytes,
+    } = transport.send(request).await?;
+
+    if status.is_success() {
+        // some endpoints (token revocation) answer with an empty body
+        let body: &[u8] = if bytes.is_empty() { b"null" } else { &bytes };
+        serde_json::from_slice(body).map_err(|source| SupremoError::Decode { status, source })
+    } else {
+        // keep non-json bodies (proxies, load balancers) as plain strings
+        let body = serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+            serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+        });
+        Err(SupremoError::from_api_error(
+            status,
+            ApiError::from_response(status, &headers, body),
+        ))
     }
 }
 
@@ -587,94 +1096,444 @@ mod tests {
         );
     }
 
-    // test auth process
-    #[tokio::test]
-    async fn test_user_auth() {
-        let client = LoanClient::new(
-            String::from("http://localhost:8080"),
-            String::from("QX5MgtTRCY48Nk7oMsXlDawofy2qmP8ngyjf8RMfVS62oaHFAq"),
-            String::from("eplvesJPuZSS9oOkNQM1pLmZBvazv"),
-            String::from("access"),
-            String::from("logo_url"),
-            String::from("http://127.0.0.1:8020/"),
+    #[test]
+    fn builder_validates_fields() {
+        let builder = || {
+            LoanClient::builder()
+                .base_url("http://localhost:8080/")
+                .public_key("public_key")
+                .secret_key("secret_key")
+                .name("bank_name")
+                .redirect_url("http://127.0.0.1:8020/")
+        };
+
+        let client = builder().logo_url("logo_url").build().unwrap();
+        assert_eq!(client.base_url, "http://localhost:8080");
+        assert_eq!(client.redirect_url, "http://127.0.0.1:8020/");
+        assert_eq!(client.logo_url.as_deref(), Some("logo_url"));
+
+        let err = builder().secret_key(" ").build().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: secret_key must not be empty"
         );
 
-        let user = client.exchange_code_auth("w59HPRYvCiE49eeGjazFIw==").await;
-        match user {
-            Ok(user) => {
-                println!("user {:?}", user);
-            }
-            Err(e) => {
-                println!("error {:?}", e.to_string());
-            }
-        }
+        let err = builder().base_url("ftp://localhost").build().unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+
+        let err = builder()
+            .retry_policy(RetryPolicy::default().multiplier(f64::NAN))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+
+        let err = LoanClient::builder().build().unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
     }
 
-    #[tokio::test]
-    async fn test_get_anchors() {
-        let client = LoanClient::new(
-            String::from("http://localhost:8080"),
-            String::from("QX5MgtTRCY48Nk7oMsXlDawofy2qmP8ngyjf8RMfVS62oaHFAq"),
-            String::from("eplvesJPuZSS9oOkNQM1pLmZBvazv"),
-            String::from("access"),
-            String::from("logo_url"),
-            String::from("http://127.0.0.1:8020/"),
+    #[test]
+    fn debug_output_redacts_secrets() {
+        let client = LoanClient::builder()
+            .base_url("http://localhost:8080/")
+            .public_key("public_key")
+            .secret_key("super_secret_key")
+            .name("bank_name")
+            .redirect_url("http://127.0.0.1:8020/")
+            .build()
+            .unwrap();
+        let debug = format!("{:?}", client);
+        assert!(!debug.contains("super_secret_key"));
+        assert!(debug.contains("secret_key: [REDACTED]"));
+
+        let token: OuathToken = serde_json::from_value(json!({
+            "access_token": "raw_access_token",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+            "scope": "read",
+        }))
+        .unwrap();
+        assert!(!format!("{:?}", token).contains("raw_access_token"));
+        assert_eq!(token.access_token.expose(), "raw_access_token");
+        assert_eq!(
+            serde_json::to_value(&token).unwrap()["access_token"],
+            "raw_access_token"
         );
-
-        let user = client
-            .get_anchors("uc3zfHDuYEmzhn9FM7lwNQ==", 1, None)
-            .await;
-        match user {
-            Ok(user) => {
-                println!("user {:?}", user);
-            }
-            Err(e) => {
-                println!("error {:?}", e.to_string());
-            }
-        }
     }
 
     #[tokio::test]
-    async fn test_client_limits() {
+    async fn managed_token_requires_token_manager() {
         let client = LoanClient::new(
             String::from("http://localhost:8080"),
-            String::from("QX5MgtTRCY48Nk7oMsXlDawofy2qmP8ngyjf8RMfVS62oaHFAq"),
-            String::from("eplvesJPuZSS9oOkNQM1pLmZBvazv"),
-            String::from("access"),
+            String::from("secret_key"),
+            String::from("public_key"),
+            String::from("bank_name"),
             String::from("logo_url"),
             String::from("http://127.0.0.1:8020/"),
         );
-
-        let user = client.client_limit("uc3zfHDuYEmzhn9FM7lwNQ==", 1).await;
-        match user {
-            Ok(user) => {
-                println!("user {:?}", user);
-            }
-            Err(e) => {
-                println!("error {:?}", e.to_string());
-            }
-        }
+        let err = client.client_limit(None, 1).await.unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+        let err = client.calculate_loan(None, vec![]).await.unwrap_err();
+        assert!(matches!(err, SupremoError::Validation(_)));
     }
 
     #[tokio::test]
-    async fn test_auth_token() {
+    async fn callback_with_wrong_state_is_rejected() {
         let client = LoanClient::new(
             String::from("http://localhost:8080"),
-            String::from("QX5MgtTRCY48Nk7oMsXlDawofy2qmP8ngyjf8RMfVS62oaHFAq"),
-            String::from("eplvesJPuZSS9oOkNQM1pLmZBvazv"),
-            String::from("access"),
+            String::from("secret_key"),
+            String::from("public_key"),
+            String::from("bank_name"),
             String::from("logo_url"),
             String::from("http://127.0.0.1:8020/"),
         );
+        let request = client.authorization_url(None).unwrap();
+        assert!(request
+            .url
+            .contains("redirect_uri=http%3A%2F%2F127.0.0.1%3A8020%2F"));
+
+        let err = client
+            .exchange_authorization_callback(&request, "code", Some("forged"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SupremoError::InvalidState(_)));
+    }
 
-        let ouath = client.get_auth_token().await;
-        match ouath {
-            Ok(ouath) => {
-                println!("ouath {:?}", ouath);
-            }
-            Err(e) => {
-                println!("error {:?}", e.to_string());
+    #[tokio::test]
+    async fn public_client_uses_pkce_without_secret() {
+        let client = LoanClient::builder()
+            .base_url("http://localhost:8080")
+            .public_key("public_key")
+            .name("bank_name")
+            .redirect_url("http://127.0.0.1:8020/")
+            .public_client()
+            .build()
+            .unwrap();
+
+        let request = client.authorization_url(None).unwrap();
+        let verifier = request.code_verifier.clone().unwrap();
+        let challenge = PkceChallenge::from_verifier(verifier).challenge;
+        assert!(request.url.ends_with(&format!(
+            "code_challenge={}&code_challenge_method=S256",
+            challenge
+        )));
+
+        let err = client.get_auth_token().await.unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+    }
+
+    // the remaining tests go through http against a local mock lender
+    #[cfg(feature = "actix")]
+    mod mock_lender {
+        use super::*;
+        use crate::api::fake::{anchor, Failure, FakeLender};
+        use crate::api::mock_server::{MockServer, MOCK_PUBLIC_KEY, MOCK_SECRET_KEY};
+        use crate::api::secrets::SecretFilesProvider;
+
+        // test auth process
+        #[tokio::test]
+        async fn test_user_auth() {
+            let user = OuathUser {
+                id: 1,
+                email: "borrower@example.com".to_string(),
+                company_name: "Borrower Ltd".to_string(),
+                anchor_id: 3,
+            };
+            let lender = FakeLender::new().with_user("w59HPRYvCiE49eeGjazFIw==", user.clone());
+            let server = MockServer::start(lender).unwrap();
+            let client = server.client();
+
+            let session = client
+                .exchange_code_auth("w59HPRYvCiE49eeGjazFIw==")
+                .await
+                .unwrap();
+            assert_eq!(session.user, user);
+            assert!(!session.token.refresh_token.is_empty());
+
+            // codes are single use
+            let err = client
+                .exchange_code_auth("w59HPRYvCiE49eeGjazFIw==")
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InvalidGrant));
+        }
+
+        fn borrower() -> OuathUser {
+            OuathUser {
+                id: 1,
+                email: "borrower@example.com".to_string(),
+                company_name: "Borrower Ltd".to_string(),
+                anchor_id: 3,
             }
         }
+
+        #[tokio::test]
+        async fn refreshed_user_token_is_rotated() {
+            let server =
+                MockServer::start(FakeLender::new().with_user("code-1", borrower())).unwrap();
+            let client = server.client();
+            let session = client.exchange_code_auth("code-1").await.unwrap();
+
+            let refreshed = client.refresh_user_token(&session.token).await.unwrap();
+            assert_ne!(refreshed.refresh_token, session.token.refresh_token);
+            assert_ne!(refreshed.access_token, session.token.access_token);
+            let user = client
+                .get_user_info(refreshed.access_token.expose())
+                .await
+                .unwrap();
+            assert_eq!(user, borrower());
+
+            // the rotated refresh token replaced the first one
+            let err = client.refresh_user_token(&session.token).await.unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InvalidGrant));
+        }
+
+        #[tokio::test]
+        async fn refresh_token_is_kept_when_not_rotated() {
+            let lender = FakeLender::new()
+                .with_user("code-1", borrower())
+                .keep_refresh_tokens();
+            let server = MockServer::start(lender).unwrap();
+            let client = server.client();
+            let session = client.exchange_code_auth("code-1").await.unwrap();
+
+            let refreshed = client.refresh_user_token(&session.token).await.unwrap();
+            assert_eq!(refreshed.refresh_token, session.token.refresh_token);
+            assert_ne!(refreshed.access_token, session.token.access_token);
+            assert!(client.refresh_user_token(&refreshed).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn revalidation_reports_a_new_anchor() {
+            let lender = FakeLender::new().with_user("code-1", borrower());
+            let server = MockServer::start(lender.clone()).unwrap();
+            let client = server.client();
+            let session = client.exchange_code_auth("code-1").await.unwrap();
+            let mut account = LinkedAccount::new("mock lender", session);
+
+            assert!(client
+                .revalidate_account(&mut account)
+                .await
+                .unwrap()
+                .is_empty());
+
+            lender.update_user(OuathUser {
+                anchor_id: 4,
+                ..borrower()
+            });
+            // an expired token is refreshed before the profile is read
+            account.token.expires_at = 0;
+            let refresh_token = account.token.refresh_token.clone();
+            let changes = client.revalidate_account(&mut account).await.unwrap();
+            assert_eq!(changes, [AccountChange::AnchorId { old: 3, new: 4 }]);
+            assert_eq!(account.user.anchor_id, 4);
+            assert_ne!(account.token.refresh_token, refresh_token);
+        }
+
+        #[tokio::test]
+        async fn revoked_tokens_are_introspected_as_inactive() {
+            let server =
+                MockServer::start(FakeLender::new().with_user("code-1", borrower())).unwrap();
+            let client = server.client();
+            let session = client.exchange_code_auth("code-1").await.unwrap();
+            let access_token = session.token.access_token.expose();
+            let refresh_token = session.token.refresh_token.expose();
+
+            let introspection = client.introspect_token(access_token, None).await.unwrap();
+            assert!(introspection.is_valid());
+            assert_eq!(introspection.sub.as_deref(), Some("1"));
+            assert_eq!(introspection.scopes(), ["read"]);
+            let introspection = client
+                .introspect_token(refresh_token, Some(TokenTypeHint::RefreshToken))
+                .await
+                .unwrap();
+            assert_eq!(introspection.token_type.as_deref(), Some("refresh_token"));
+
+            // the lender answers with an empty body
+            client
+                .revoke_token(access_token, Some(TokenTypeHint::AccessToken))
+                .await
+                .unwrap();
+            let introspection = client.introspect_token(access_token, None).await.unwrap();
+            assert_eq!(introspection, TokenIntrospection::default());
+            assert!(!introspection.is_valid());
+
+            let revoked = server.oauth_requests("revoke");
+            assert_eq!(revoked[0]["token"], access_token);
+            assert_eq!(revoked[0]["token_type_hint"], "access_token");
+            let introspected = server.oauth_requests("introspect");
+            assert!(!introspected[0].contains_key("token_type_hint"));
+            assert_eq!(introspected[1]["token_type_hint"], "refresh_token");
+        }
+
+        #[tokio::test]
+        async fn test_get_anchors() {
+            let lender = FakeLender::new()
+                .with_client(1, 10_000.0)
+                .with_anchor(1, anchor(3, 5_000.0))
+                .with_anchor(1, anchor(4, 5_000.0));
+            let server = MockServer::start(lender).unwrap();
+            let client = server.client();
+            let token = client.get_auth_token().await.unwrap().access_token;
+
+            let anchors = client
+                .get_anchors(token.expose(), 1, Some(AnchorPagination::default()))
+                .await
+                .unwrap();
+            assert_eq!(anchors.total, 2);
+            assert_eq!(anchors.data[0].anchor_id, 4);
+
+            let err = client
+                .get_anchors("uc3zfHDuYEmzhn9FM7lwNQ==", 1, None)
+                .await
+                .unwrap_err();
+            assert!(err.is_auth_expired());
+        }
+
+        #[tokio::test]
+        async fn test_client_limits() {
+            let lender = FakeLender::new()
+                .with_client(1, 10_000.0)
+                .with_anchor(1, anchor(3, 5_000.0));
+            let server = MockServer::start(lender.clone()).unwrap();
+            let client = server.client().with_retry_policy(
+                RetryPolicy::default().backoff(Duration::from_millis(1), Duration::from_millis(5)),
+            );
+            let token = client.get_auth_token().await.unwrap().access_token;
+
+            let loan = LoanInput {
+                amount: 1_000.0,
+                anchor_id: 3,
+                client_id: 1,
+                loan_term: 30,
+                loan_type: "api_request".to_string(),
+                metadata: json!({}),
+            };
+            let charges = client
+                .calculate_loan(token.expose().to_string(), vec![loan.clone()])
+                .await
+                .unwrap();
+            assert_eq!(charges[0].interest_amount, 40.0);
+
+            // a server error is retried when the application carries a key
+            lender.fail_next(Endpoint::ApplyLoan, Failure::ServerError);
+            let key = IdempotencyKey::for_batch(std::slice::from_ref(&loan));
+            client
+                .apply_for_loan_with_key(token.expose().to_string(), vec![loan.clone()], &key)
+                .await
+                .unwrap();
+            assert_eq!(lender.calls(Endpoint::ApplyLoan), 2);
+
+            let limit = client.client_limit(token.expose(), 1).await.unwrap();
+            assert_eq!(limit.used_limit, 1_000.0);
+            assert_eq!(limit.remaining_limit, 9_000.0);
+
+            let mut large = loan;
+            large.amount = 4_500.0;
+            let err = client
+                .apply_for_loan(token.expose().to_string(), vec![large])
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InsufficientLimit));
+            assert_eq!(err.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+        }
+
+        #[tokio::test]
+        async fn test_auth_token() {
+            let server = MockServer::start(FakeLender::new().with_client(1, 100.0)).unwrap();
+            let client = server.client().with_token_manager(TokenManager::default());
+
+            let ouath = client.get_auth_token().await.unwrap();
+            assert_eq!(ouath.token_type, "Bearer");
+            assert!(client.client_limit(None, 1).await.is_ok());
+
+            let wrong_secret = LoanClient::new(
+                server.base_url(),
+                String::from("QX5MgtTRCY48Nk7oMsXlDawofy2qmP8ngyjf8RMfVS62oaHFAq"),
+                String::from("eplvesJPuZSS9oOkNQM1pLmZBvazv"),
+                String::from("access"),
+                String::from("logo_url"),
+                String::from("http://127.0.0.1:8020/"),
+            );
+            let err = wrong_secret.get_auth_token().await.unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InvalidClient));
+        }
+
+        #[tokio::test]
+        async fn slow_lender_answers_behind_mock_server() {
+            let lender = FakeLender::new().with_latency(Duration::from_millis(50));
+            let server = MockServer::start(lender).unwrap();
+
+            let started = std::time::Instant::now();
+            assert!(server.client().get_auth_token().await.is_ok());
+            assert!(started.elapsed() >= Duration::from_millis(50));
+        }
+
+        #[tokio::test]
+        async fn previous_credentials_are_tried_during_rotation() {
+            let server = MockServer::start(FakeLender::new()).unwrap();
+            let store = CredentialStore::new(Credentials::new(MOCK_PUBLIC_KEY, MOCK_SECRET_KEY));
+            let client = server.client().with_credential_store(store.clone());
+
+            // we switch first, the lender still only accepts the old key
+            store.rotate(Credentials::new(MOCK_PUBLIC_KEY, "new_secret"));
+            assert!(client.get_auth_token().await.is_ok());
+
+            server.rotate_credentials(MOCK_PUBLIC_KEY, "new_secret");
+            assert!(client.get_auth_token().await.is_ok());
+
+            // once retired the old key is no longer tried
+            store.retire_previous();
+            server.rotate_credentials(MOCK_PUBLIC_KEY, MOCK_SECRET_KEY);
+            let err = client.get_auth_token().await.unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InvalidClient));
+        }
+
+        #[tokio::test]
+        async fn rejected_credentials_are_read_again() {
+            let dir = std::env::temp_dir().join(format!("supremo-rotation-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("mock_lender_public_key"), MOCK_PUBLIC_KEY).unwrap();
+            std::fs::write(dir.join("mock_lender_secret_key"), MOCK_SECRET_KEY).unwrap();
+
+            let server = MockServer::start(FakeLender::new()).unwrap();
+            let store =
+                CredentialStore::from_provider("mock lender", SecretFilesProvider::new(&dir))
+                    .unwrap();
+            let client = server.client().with_credential_store(store.clone());
+            assert!(client.get_auth_token().await.is_ok());
+
+            // the lender rotates and the new secret is mounted, no client is rebuilt
+            server.rotate_credentials(MOCK_PUBLIC_KEY, "rotated_secret");
+            std::fs::write(dir.join("mock_lender_secret_key"), "rotated_secret").unwrap();
+            assert!(client.get_auth_token().await.is_ok());
+            assert_eq!(store.current().secret_key.expose(), "rotated_secret");
+
+            // the provider failing too does not hide why the lender refused
+            server.rotate_credentials(MOCK_PUBLIC_KEY, "unknown_secret");
+            std::fs::remove_file(dir.join("mock_lender_secret_key")).unwrap();
+            let err = client.get_auth_token().await.unwrap_err();
+            assert_eq!(err.kind(), Some(ApiErrorKind::InvalidClient));
+            assert!(store.reload_error().is_some());
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[tokio::test]
+        async fn single_use_grants_are_not_sent_again() {
+            let lender = FakeLender::new().with_user("code-1", borrower());
+            let server = MockServer::start(lender.clone()).unwrap();
+            let store = CredentialStore::new(Credentials::new(MOCK_PUBLIC_KEY, "old_secret"));
+            store.rotate(Credentials::new(MOCK_PUBLIC_KEY, MOCK_SECRET_KEY));
+            let client = server.client().with_credential_store(store);
+
+            lender.fail_next(Endpoint::ExchangeCode, Failure::ExpiredToken);
+            let err = client.exchange_code_auth("code-1").await.unwrap_err();
+            assert!(err.is_auth_expired());
+            assert_eq!(lender.calls(Endpoint::ExchangeCode), 1);
+
+            // a rejected client is tried again, the code was not consumed
+            server.rotate_credentials(MOCK_PUBLIC_KEY, "old_secret");
+            assert!(client.exchange_code_auth("code-1").await.is_ok());
+        }
     }
 }
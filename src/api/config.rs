@@ -0,0 +1,825 @@
+//! Typed lender configuration loaded from TOML, YAML or JSON files.
+//!
+//! A configuration file holds a `lenders` list, each entry a [`LenderConfig`]. Every
+//! invalid field is reported, each error pointing at the offending entry and field, with
+//! the line in the file when the format reports one, e.g. `lenders[1].base_url (line 9,
+//! column 12): "ftp://lender" is not an http(s) url`.
+//!
+//! ```toml
+//! [[lenders]]
+//! name = "access bank"
+//! base_url = "https://lender.example.com"
+//! redirect_url = "https://app.example.com/callback"
+//! environment = "staging"
+//! public_key = { env = "ACCESS_PUBLIC_KEY" }
+//! secret_key = { file = "/run/secrets/access_secret_key" }
+//!
+//! [lenders.timeouts]
+//! connect_secs = 5
+//!
+//! [lenders.retry]
+//! max_attempts = 4
+//!
+//! [lenders.tokens]
+//! refresh_before_secs = 120
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use zeroize::Zeroize;
+
+use super::client::LoanClient;
+use super::error::{Result, SupremoError};
+use super::registry::LenderMetadata;
+use super::retry::RetryPolicy;
+use super::secrets::{EnvProvider, KeyKind, SecretProvider, SecretString};
+use super::transport::Transport;
+
+/// Where a key is read from.
+///
+/// Written as `{ env = "NAME" }`, `{ file = "/path" }` or `{ value = "..." }` in TOML,
+/// and the equivalent maps in YAML and JSON. Keys left out of the configuration are looked
+/// up in a [`SecretProvider`], by default `{NAME}_PUBLIC_KEY` and `{NAME}_SECRET_KEY`
+/// like [`add_clients_keys`](super::actions::add_clients_keys).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRef {
+    /// environment variable
+    Env(String),
+    /// file holding the key, surrounding whitespace is ignored
+    File(PathBuf),
+    /// the key itself, keep it out of files that are committed
+    Value(SecretString),
+}
+
+impl KeyRef {
+    pub fn resolve(&self) -> Result<SecretString> {
+        match self {
+            KeyRef::Env(name) => std::env::var(name).map(SecretString::from).map_err(|_| {
+                SupremoError::Config(format!("environment variable {} is not set", name))
+            }),
+            KeyRef::File(path) => fs::read_to_string(path)
+                .map(|mut key| {
+                    let secret = SecretString::from(key.trim());
+                    key.zeroize();
+                    secret
+                })
+                .map_err(|e| {
+                    SupremoError::Config(format!("could not read key {}: {}", path.display(), e))
+                }),
+            KeyRef::Value(key) => Ok(key.clone()),
+        }
+    }
+}
+
+/// Deployment the lender entry belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Environment {
+    #[default]
+    Production,
+    Staging,
+    Sandbox,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    #[serde(deserialize_with = "positive")]
+    pub connect_secs: u64,
+    #[serde(deserialize_with = "positive")]
+    pub request_secs: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            connect_secs: 10,
+            request_secs: 30,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// `1` sends every request once
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub jitter: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 5_000,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+            .max_attempts(self.max_attempts)
+            .backoff(
+                Duration::from_millis(self.initial_backoff_ms),
+                Duration::from_millis(self.max_backoff_ms),
+            )
+            .jitter(self.jitter)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenConfig {
+    /// cache the client-credentials token, so calls without a bearer token fetch one
+    pub manage: bool,
+    /// refresh the cached token this long before it expires
+    pub refresh_before_secs: u64,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        TokenConfig {
+            manage: true,
+            refresh_before_secs: 60,
+        }
+    }
+}
+
+/// Configuration of one lender.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LenderConfig {
+    #[serde(deserialize_with = "non_empty")]
+    pub name: String,
+    #[serde(deserialize_with = "http_url")]
+    pub base_url: String,
+    #[serde(deserialize_with = "http_url")]
+    pub redirect_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo_url: Option<String>,
+    /// name shown to borrowers, defaults to `name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// loan types the lender accepts, empty when not restricted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub loan_types: Vec<String>,
+    /// disabled lenders are kept in a [`LenderRegistry`](super::registry::LenderRegistry) without
+    /// being handed out
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<KeyRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<KeyRef>,
+    #[serde(default)]
+    pub environment: Environment,
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub tokens: TokenConfig,
+}
+
+impl LenderConfig {
+    /// resolve the keys and build the client with the configured timeouts, retries and
+    /// token management, keys left out of the configuration are read from `{NAME}_PUBLIC_KEY` and `{NAME}_SECRET_KEY`
+    pub fn client(&self) -> Result<LoanClient> {
+        self.client_with(&EnvProvider::default())
+    }
+
+    /// like [`LenderConfig::client`], looking up keys left out of the configuration in `secrets`
+    pub fn client_with(&self, secrets: &dyn SecretProvider) -> Result<LoanClient> {
+        let key = |configured: &Option<KeyRef>, kind| match configured {
+            Some(key) => key.resolve(),
+            None => secrets.require(&self.name, kind),
+        };
+        let transport = Transport::builder()
+            .connect_timeout(Duration::from_secs(self.timeouts.connect_secs))
+            .timeout(Duration::from_secs(self.timeouts.request_secs))
+            .build()?;
+        let mut builder = LoanClient::builder()
+            .name(&self.name)
+            .base_url(&self.base_url)
+            .redirect_url(&self.redirect_url)
+            .public_key(key(&self.public_key, KeyKind::PublicKey)?.expose())
+            .secret_key(key(&self.secret_key, KeyKind::SecretKey)?)
+            .transport(transport)
+            .retry_policy(self.retry.policy());
+        if let Some(logo_url) = &self.logo_url {
+            builder = builder.logo_url(logo_url);
+        }
+        if self.tokens.manage {
+            builder = builder.manage_tokens(Duration::from_secs(self.tokens.refresh_before_secs));
+        }
+        builder.build()
+    }
+
+    pub fn metadata(&self) -> LenderMetadata {
+        LenderMetadata {
+            display_name: self
+                .display_name
+                .clone()
+                .unwrap_or_else(|| self.name.clone()),
+            logo_url: self.logo_url.clone(),
+            loan_types: self.loan_types.clone(),
+        }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if value.trim().is_empty() {
+        return Err(D::Error::custom("must not be empty"));
+    }
+    Ok(value)
+}
+
+/// a zero timeout fails every request right away
+fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u64, D::Error> {
+    let value = u64::deserialize(deserializer)?;
+    if value == 0 {
+        return Err(D::Error::custom("must be greater than 0"));
+    }
+    Ok(value)
+}
+
+fn http_url<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    match reqwest::Url::parse(value.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Ok(value),
+        _ => Err(D::Error::custom(format!(
+            "{:?} is not an http(s) url",
+            value
+        ))),
+    }
+}
+
+/// Format of a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// format matching the extension of `path`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Invalid configuration, pointing at the field at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// index of the lender entry, when the error is inside one
+    pub lender: Option<usize>,
+    /// path of the field, e.g. `lenders[1].base_url`, empty for errors about the whole file
+    pub path: String,
+    /// 1-based position in the file, when the format reports it
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn file(message: String) -> Self {
+        ConfigError {
+            lender: None,
+            path: String::new(),
+            line: None,
+            column: None,
+            message,
+        }
+    }
+
+    /// error in the lender entry at `index`, `field` is empty for the entry itself
+    fn lender(index: usize, field: &str, message: String) -> Self {
+        let path = match field {
+            "" | "." => format!("lenders[{}]", index),
+            field => format!("lenders[{}].{}", index, field),
+        };
+        ConfigError {
+            lender: Some(index),
+            path,
+            line: None,
+            column: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}", self.path)?;
+        }
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, " (line {}, column {})", line, column)?,
+            (Some(line), None) => write!(f, " (line {})", line)?,
+            _ => {}
+        }
+        if !self.path.is_empty() || self.line.is_some() {
+            f.write_str(": ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for SupremoError {
+    fn from(error: ConfigError) -> Self {
+        SupremoError::Config(error.to_string())
+    }
+}
+
+/// Every invalid field of a configuration file, ordered by lender entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl Deref for ConfigErrors {
+    type Target = [ConfigError];
+
+    fn deref(&self) -> &[ConfigError] {
+        &self.0
+    }
+}
+
+impl From<ConfigError> for ConfigErrors {
+    fn from(error: ConfigError) -> Self {
+        ConfigErrors(vec![error])
+    }
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, error) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+impl From<ConfigErrors> for SupremoError {
+    fn from(errors: ConfigErrors) -> Self {
+        SupremoError::Config(errors.to_string())
+    }
+}
+
+/// Lenders listed in a configuration file.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::config::{ConfigFormat, LendersConfig};
+///
+/// let config = LendersConfig::parse(
+///     r#"{"lenders": [{"name": "bank name", "base_url": "https://lender.example.com",
+///                      "redirect_url": "https://app.example.com/callback"}]}"#,
+///     ConfigFormat::Json,
+/// )
+/// .unwrap();
+/// assert_eq!(config.lenders[0].name, "bank name");
+///
+/// let err = LendersConfig::parse(
+///     "{\"lenders\": [{\"name\": \"bank name\",\n\"base_url\": \"lender\"}]}",
+///     ConfigFormat::Json,
+/// )
+/// .unwrap_err();
+/// assert_eq!(err.len(), 2);
+/// assert_eq!(err[0].lender, Some(0));
+/// assert_eq!(err[0].path, "lenders[0].base_url");
+/// assert_eq!(err[0].line, Some(2));
+/// assert_eq!(err[1].message, "missing field `redirect_url`");
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LendersConfig {
+    pub lenders: Vec<LenderConfig>,
+}
+
+impl LendersConfig {
+    /// read `path`, the format is picked from its extension
+    pub fn from_path(path: impl AsRef<Path>) -> std::result::Result<Self, ConfigErrors> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path).ok_or_else(|| {
+            ConfigError::file(format!(
+                "{} must end in .toml, .yaml, .yml or .json",
+                path.display()
+            ))
+        })?;
+        let content = fs::read_to_string(path)
+            .map_err(|e| ConfigError::file(format!("could not read {}: {}", path.display(), e)))?;
+        Self::parse(&content, format)
+    }
+
+    pub fn parse(content: &str, format: ConfigFormat) -> std::result::Result<Self, ConfigErrors> {
+        match deserialize::<Self>(content, format) {
+            Ok(config) => {
+                let errors = check_names(config.lenders.iter().enumerate());
+                if errors.is_empty() {
+                    Ok(config)
+                } else {
+                    Err(ConfigErrors(errors))
+                }
+            }
+            Err(first) => Err(ConfigErrors(collect_errors(content, format, first))),
+        }
+    }
+
+    /// the lender named `name`, regardless of case
+    pub fn get(&self, name: &str) -> Option<&LenderConfig> {
+        let name = name_key(name);
+        self.lenders
+            .iter()
+            .find(|lender| name_key(&lender.name) == name)
+    }
+
+    pub fn for_environment(
+        &self,
+        environment: Environment,
+    ) -> impl Iterator<Item = &LenderConfig> + '_ {
+        self.lenders
+            .iter()
+            .filter(move |lender| lender.environment == environment)
+    }
+
+    /// build a client for every lender, failing on the first lender that cannot be built
+    pub fn clients(&self) -> Result<Vec<LoanClient>> {
+        self.lenders.iter().map(LenderConfig::client).collect()
+    }
+}
+
+/// lender names are compared regardless of case
+fn name_key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// lender names identify clients, so they must be unique
+fn check_names<'a>(lenders: impl Iterator<Item = (usize, &'a LenderConfig)>) -> Vec<ConfigError> {
+    let mut seen = HashSet::new();
+    lenders
+        .filter(|(_, lender)| !seen.insert(name_key(&lender.name)))
+        .map(|(index, lender)| {
+            ConfigError::lender(
+                index,
+                "name",
+                format!("lender {:?} is configured more than once", lender.name),
+            )
+        })
+        .collect()
+}
+
+/// every invalid field of a file that failed with `first`
+///
+/// formats stop at the first error, so the entries are read again one by one, replacing
+/// each invalid field until the entry is complete. `first` keeps its position in the file,
+/// the other errors have none.
+fn collect_errors(content: &str, format: ConfigFormat, first: ConfigError) -> Vec<ConfigError> {
+    #[derive(Deserialize)]
+    struct Entries {
+        #[serde(default)]
+        lenders: Vec<serde_json::Value>,
+    }
+
+    let Ok(entries) = deserialize::<Entries>(content, format) else {
+        return vec![first];
+    };
+    let mut errors = Vec::new();
+    let mut valid = Vec::new();
+    for (index, entry) in entries.lenders.into_iter().enumerate() {
+        match lender_errors(index, entry) {
+            Ok(lender) => valid.push((index, lender)),
+            Err(lender_errors) => errors.extend(lender_errors),
+        }
+    }
+    errors.extend(check_names(
+        valid.iter().map(|(index, lender)| (*index, lender)),
+    ));
+    // `first` replaces its counterpart and stays first within its entry
+    if let Some(index) = errors.iter().position(|error| error.path == first.path) {
+        errors.remove(index);
+    }
+    errors.insert(0, first);
+    errors.sort_by_key(|error| error.lender);
+    errors
+}
+
+/// valid stand-ins for required fields, so the fields after them are still checked
+fn placeholder(field: &str) -> Option<&'static str> {
+    match field {
+        "name" => Some("invalid"),
+        "base_url" | "redirect_url" => Some("http://invalid"),
+        _ => None,
+    }
+}
+
+fn lender_errors(
+    index: usize,
+    mut entry: serde_json::Value,
+) -> std::result::Result<LenderConfig, Vec<ConfigError>> {
+    let mut errors = Vec::new();
+    if let Some(fields) = entry.as_object_mut() {
+        for field in ["name", "base_url", "redirect_url"] {
+            if !fields.contains_key(field) {
+                errors.push(ConfigError::lender(
+                    index,
+                    "",
+                    format!("missing field `{}`", field),
+                ));
+                fields.insert(field.to_string(), placeholder(field).into());
+            }
+        }
+    }
+    loop {
+        let error = match serde_path_to_error::deserialize::<_, LenderConfig>(&entry) {
+            Ok(lender) if errors.is_empty() => return Ok(lender),
+            Ok(_) => return Err(errors),
+            Err(error) => error,
+        };
+        errors.push(ConfigError::lender(
+            index,
+            &error.path().to_string(),
+            error.inner().to_string(),
+        ));
+        let field = match error.path().iter().next() {
+            Some(serde_path_to_error::Segment::Map { key }) => key.clone(),
+            _ => return Err(errors),
+        };
+        let Some(fields) = entry.as_object_mut() else {
+            return Err(errors);
+        };
+        match placeholder(&field) {
+            Some(placeholder) if fields.get(&field) != Some(&placeholder.into()) => {
+                fields.insert(field, placeholder.into());
+            }
+            Some(_) => return Err(errors),
+            None if fields.remove(&field).is_some() => {}
+            None => return Err(errors),
+        }
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(
+    content: &str,
+    format: ConfigFormat,
+) -> std::result::Result<T, ConfigError> {
+    match format {
+        ConfigFormat::Toml => parse_toml(content),
+        ConfigFormat::Yaml => parse_yaml(content),
+        ConfigFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(content);
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+                let (line, column) = (e.inner().line(), e.inner().column());
+                field_error(e.path(), Some((line, column)), e.inner().to_string())
+            })
+        }
+    }
+}
+
+fn field_error(
+    path: &serde_path_to_error::Path,
+    position: Option<(usize, usize)>,
+    message: String,
+) -> ConfigError {
+    let lender = path.iter().nth(1).and_then(|segment| match segment {
+        serde_path_to_error::Segment::Seq { index } => Some(*index),
+        _ => None,
+    });
+    let path = path.to_string();
+    let path = if path == "." { String::new() } else { path };
+    // formats append the position and sometimes prefix the path, both are reported separately
+    let mut message = message;
+    if let Some((line, column)) = position {
+        let suffix = format!(" at line {} column {}", line, column);
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+    }
+    if let Some((prefix, rest)) = message.split_once(": ") {
+        if path.starts_with(prefix) {
+            message = rest.to_string();
+        }
+    }
+    ConfigError {
+        lender,
+        path,
+        line: position.map(|(line, _)| line),
+        column: position.map(|(_, column)| column),
+        message,
+    }
+}
+
+#[cfg(feature = "toml")]
+fn parse_toml<T: DeserializeOwned>(content: &str) -> std::result::Result<T, ConfigError> {
+    serde_path_to_error::deserialize(toml::Deserializer::new(content)).map_err(|e| {
+        let position = e.inner().span().map(|span| {
+            let before = &content[..span.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            (line, column)
+        });
+        field_error(e.path(), position, e.inner().message().to_string())
+    })
+}
+
+#[cfg(not(feature = "toml"))]
+fn parse_toml<T>(_content: &str) -> std::result::Result<T, ConfigError> {
+    Err(ConfigError::file(
+        "toml configuration needs the toml feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "yaml")]
+fn parse_yaml<T: DeserializeOwned>(content: &str) -> std::result::Result<T, ConfigError> {
+    serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(content)).map_err(|e| {
+        let position = e
+            .inner()
+            .location()
+            .map(|location| (location.line(), location.column()));
+        field_error(e.path(), position, e.inner().to_string())
+    })
+}
+
+#[cfg(not(feature = "yaml"))]
+fn parse_yaml<T>(_content: &str) -> std::result::Result<T, ConfigError> {
+    Err(ConfigError::file(
+        "yaml configuration needs the yaml feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "toml")]
+    const TOML: &str = r#"
+[[lenders]]
+name = "access bank"
+base_url = "https://lender.example.com"
+redirect_url = "https://app.example.com/callback"
+environment = "staging"
+public_key = { value = "public_key" }
+secret_key = { env = "SUPREMO_CONFIG_TEST_SECRET" }
+
+[lenders.timeouts]
+connect_secs = 5
+
+[lenders.retry]
+max_attempts = 4
+
+[[lenders]]
+name = "other bank"
+base_url = "ftp://lender.example.com"
+redirect_url = "https://app.example.com/callback"
+"#;
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn toml_errors_point_at_the_lender_and_line() {
+        let err = LendersConfig::parse(TOML, ConfigFormat::Toml).unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert_eq!(err[0].lender, Some(1));
+        assert_eq!(err[0].path, "lenders[1].base_url");
+        assert_eq!(err[0].line, Some(18));
+        assert_eq!(
+            err.to_string(),
+            r#"lenders[1].base_url (line 18, column 12): "ftp://lender.example.com" is not an http(s) url"#
+        );
+
+        let valid = TOML.replace("ftp://", "https://");
+        let config = LendersConfig::parse(&valid, ConfigFormat::Toml).unwrap();
+        let lender = config.get("Access Bank").unwrap();
+        assert_eq!(lender.environment, Environment::Staging);
+        assert_eq!(lender.timeouts.connect_secs, 5);
+        assert_eq!(lender.timeouts.request_secs, 30);
+        assert_eq!(lender.retry.max_attempts, 4);
+        let secrets = crate::api::secrets::DotenvProvider::parse(
+            "OTHER_BANK_PUBLIC_KEY=other_public\nOTHER_BANK_SECRET_KEY=other_secret",
+        );
+        let other = config.lenders[1].client_with(&secrets).unwrap();
+        assert_eq!(other.public_key, "other_public");
+        assert_eq!(config.for_environment(Environment::Production).count(), 1);
+
+        std::env::set_var("SUPREMO_CONFIG_TEST_SECRET", "secret_key");
+        let client = lender.client().unwrap();
+        assert_eq!(client.public_key, "public_key");
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn yaml_reports_unknown_and_missing_fields() {
+        let yaml = "lenders:\n  - name: access bank\n    base_url: https://lender.example.com\n    redirect_url: https://app.example.com/callback\n    base_ulr: typo\n";
+        let err = LendersConfig::parse(yaml, ConfigFormat::Yaml).unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert_eq!(err[0].path, "lenders[0].base_ulr");
+        assert!(err[0].message.starts_with("unknown field `base_ulr`"));
+        assert!(err[0].line.is_some());
+
+        let yaml = "lenders:\n  - name: access bank\n";
+        let err = LendersConfig::parse(yaml, ConfigFormat::Yaml).unwrap_err();
+        let messages: Vec<_> = err.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            ["missing field `base_url`", "missing field `redirect_url`"]
+        );
+        assert_eq!(err[0].lender, Some(0));
+        assert!(err[0].line.is_some());
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let lender = r#"{"name": "Bank", "base_url": "https://a.example.com", "redirect_url": "https://b.example.com"}"#;
+        let json = format!(
+            r#"{{"lenders": [{}, {}]}}"#,
+            lender,
+            lender.replace("Bank", "bank")
+        );
+        let err = LendersConfig::parse(&json, ConfigFormat::Json).unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert_eq!(err[0].path, "lenders[1].name");
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("lenders.yml")),
+            Some(ConfigFormat::Yaml)
+        );
+    }
+
+    #[test]
+    fn configured_keys_are_not_serialized() {
+        let config = LendersConfig::parse(
+            r#"{"lenders": [{"name": "bank name", "base_url": "https://lender.example.com",
+                             "redirect_url": "https://app.example.com/callback",
+                             "secret_key": {"value": "inline_secret_key"}}]}"#,
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        let json = serde_json::to_string(&config.lenders[0]).unwrap();
+        assert!(!json.contains("inline_secret_key"));
+        assert!(json.contains(r#""secret_key":{"value":"[REDACTED]"}"#));
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let json = r#"{"lenders": [
+            {"name": "bank a", "base_url": "lender", "redirect_url": "https://b.example.com",
+             "timeouts": {"request_secs": 0}, "enabled": "yes"},
+            {"name": "bank b", "base_url": "https://a.example.com", "redirect_url": "https://b.example.com"},
+            {"name": " ", "base_url": "https://a.example.com"},
+            {"name": "Bank B", "base_url": "https://a.example.com", "redirect_url": "https://b.example.com",
+             "timeouts": {"connect_secs": 0}}
+        ]}"#;
+        let err = LendersConfig::parse(json, ConfigFormat::Json).unwrap_err();
+        let paths: Vec<_> = err.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "lenders[0].base_url",
+                "lenders[0].enabled",
+                "lenders[0].timeouts.request_secs",
+                "lenders[2]",
+                "lenders[2].name",
+                "lenders[3].timeouts.connect_secs",
+            ]
+        );
+        assert_eq!(err[0].line, Some(2));
+        assert_eq!(err[2].message, "must be greater than 0");
+        assert_eq!(err[3].message, "missing field `redirect_url`");
+
+        // the duplicate is only found once the other fields of the entry are valid
+        let json = json.replace(r#""connect_secs": 0"#, r#""connect_secs": 1"#);
+        let err = LendersConfig::parse(&json, ConfigFormat::Json).unwrap_err();
+        assert_eq!(err.last().unwrap().path, "lenders[3].name");
+        assert!(SupremoError::from(err)
+            .to_string()
+            .contains("\"Bank B\" is configured more than once"));
+    }
+}
@@ -0,0 +1,366 @@
+//! Client credentials that can be rotated on a running client.
+//!
+//! A [`CredentialStore`] attached to a [`LoanClient`](super::client::LoanClient) holds the
+//! `public_key`/`secret_key` it authenticates with. The keys can be swapped with
+//! [`CredentialStore::rotate`], re-read from a [`SecretProvider`] on a schedule, or
+//! re-read when the lender rejects them. After a rotation the previous keys are kept for
+//! a grace period and tried again when the new ones are rejected, so a client can switch
+//! before or after the lender does.
+
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::error::Result;
+use super::secrets::{KeyKind, SecretProvider, SecretString};
+
+/// Public and secret key a client authenticates with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub public_key: String,
+    pub secret_key: SecretString,
+}
+
+impl Credentials {
+    pub fn new(public_key: impl Into<String>, secret_key: impl Into<SecretString>) -> Self {
+        Self {
+            public_key: public_key.into(),
+            secret_key: secret_key.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    current: Credentials,
+    /// retired credentials and until when they are still tried
+    previous: Option<(Credentials, Instant)>,
+    /// last rotation or reload attempt, failed ones included
+    checked_at: Instant,
+    /// why the last reload failed, cleared by the next successful one
+    reload_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    lender: String,
+    provider: Arc<dyn SecretProvider>,
+}
+
+/// Credentials shared by a client and its clones, swappable at runtime.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use supremo_loan::api::credentials::{CredentialStore, Credentials};
+///
+/// let store = CredentialStore::new(Credentials::new("public_key", "old_secret"))
+///     .with_grace_period(Duration::from_secs(3600));
+/// store.rotate(Credentials::new("public_key", "new_secret"));
+///
+/// assert_eq!(store.current().secret_key.expose(), "new_secret");
+/// assert_eq!(store.previous().unwrap().secret_key.expose(), "old_secret");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    state: Arc<RwLock<State>>,
+    source: Option<Source>,
+    grace_period: Duration,
+    reload_every: Option<Duration>,
+}
+
+impl CredentialStore {
+    /// keep the previous credentials for a day after a rotation by default
+    pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new(credentials: Credentials) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(State {
+                current: credentials,
+                previous: None,
+                checked_at: Instant::now(),
+                reload_error: None,
+            })),
+            source: None,
+            grace_period: Self::DEFAULT_GRACE_PERIOD,
+            reload_every: None,
+        }
+    }
+
+    /// read the keys of `lender` from `provider`, which [`CredentialStore::reload`] reads again
+    pub fn from_provider(lender: &str, provider: impl SecretProvider + 'static) -> Result<Self> {
+        let provider: Arc<dyn SecretProvider> = Arc::new(provider);
+        let mut store = Self::new(Self::read(lender, provider.as_ref())?);
+        store.source = Some(Source {
+            lender: lender.to_string(),
+            provider,
+        });
+        Ok(store)
+    }
+
+    /// how long the previous credentials are still tried after a rotation
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// re-read the provider when the credentials are older than `interval`, checked on use
+    ///
+    /// the due reload runs on the request that finds it due, the others keep the current
+    /// credentials meanwhile; the provider is called synchronously there, so it must be
+    /// cheap (environment, mounted files), call [`CredentialStore::reload`] from a
+    /// background task instead for slow ones such as a remote vault
+    pub fn reload_every(mut self, interval: Duration) -> Self {
+        self.reload_every = Some(interval);
+        self
+    }
+
+    fn read(lender: &str, provider: &dyn SecretProvider) -> Result<Credentials> {
+        Ok(Credentials {
+            public_key: provider
+                .require(lender, KeyKind::PublicKey)?
+                .expose()
+                .to_string(),
+            secret_key: provider.require(lender, KeyKind::SecretKey)?,
+        })
+    }
+
+    /// the credentials to authenticate with, re-read first when they are due for a reload
+    ///
+    /// a failed scheduled reload keeps the current credentials and is tried again after
+    /// another interval, see [`CredentialStore::reload_error`]
+    pub fn current(&self) -> Credentials {
+        if self.claim_reload() {
+            // the error is kept for reload_error
+            let _ = self.reload();
+        }
+        self.state.read().unwrap().current.clone()
+    }
+
+    /// whether a reload is due, marking it started so only one caller reloads
+    fn claim_reload(&self) -> bool {
+        let Some(interval) = self.reload_every else {
+            return false;
+        };
+        if self.state.read().unwrap().checked_at.elapsed() < interval {
+            return false;
+        }
+        let mut state = self.state.write().unwrap();
+        if state.checked_at.elapsed() < interval {
+            return false;
+        }
+        state.checked_at = Instant::now();
+        true
+    }
+
+    /// why the last reload from the provider failed, `None` once one succeeds
+    pub fn reload_error(&self) -> Option<String> {
+        self.state.read().unwrap().reload_error.clone()
+    }
+
+    /// the credentials replaced by the last rotation, while in their grace period
+    pub fn previous(&self) -> Option<Credentials> {
+        let state = self.state.read().unwrap();
+        state
+            .previous
+            .as_ref()
+            .filter(|(_, until)| Instant::now() < *until)
+            .map(|(credentials, _)| credentials.clone())
+    }
+
+    /// switch to `credentials`, the current ones are kept for the grace period
+    ///
+    /// rotating to the current credentials changes nothing, returns whether they changed
+    pub fn rotate(&self, credentials: Credentials) -> bool {
+        self.rotate_locked(&mut self.state.write().unwrap(), credentials)
+    }
+
+    fn rotate_locked(&self, state: &mut State, credentials: Credentials) -> bool {
+        state.checked_at = Instant::now();
+        if state.current == credentials {
+            return false;
+        }
+        let retired = std::mem::replace(&mut state.current, credentials);
+        state.previous = Some((retired, Instant::now() + self.grace_period));
+        true
+    }
+
+    /// stop trying the previous credentials, e.g. once the lender revoked them
+    pub fn retire_previous(&self) {
+        self.state.write().unwrap().previous = None;
+    }
+
+    /// read the keys from the provider again and rotate when they changed
+    ///
+    /// returns whether the credentials changed, always `false` without a provider
+    pub fn reload(&self) -> Result<bool> {
+        let Some(source) = &self.source else {
+            return Ok(false);
+        };
+        let credentials = match Self::read(&source.lender, source.provider.as_ref()) {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                let mut state = self.state.write().unwrap();
+                state.checked_at = Instant::now();
+                state.reload_error = Some(e.to_string());
+                return Err(e);
+            }
+        };
+        let mut state = self.state.write().unwrap();
+        state.reload_error = None;
+        Ok(self.rotate_locked(&mut state, credentials))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::error::SupremoError;
+    use crate::api::secrets::DotenvProvider;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// provider whose `.env` content can be replaced, as a rotated secret, or removed to
+    /// make reads fail
+    #[derive(Debug, Clone)]
+    struct Rotating(Arc<Mutex<Option<DotenvProvider>>>, Arc<AtomicUsize>);
+
+    impl Rotating {
+        fn new(secret_key: &str) -> Self {
+            Self(
+                Arc::new(Mutex::new(Some(Self::keys(secret_key)))),
+                Arc::new(AtomicUsize::new(0)),
+            )
+        }
+
+        fn keys(secret_key: &str) -> DotenvProvider {
+            DotenvProvider::parse(&format!(
+                "BANK_PUBLIC_KEY=public_key\nBANK_SECRET_KEY={}",
+                secret_key
+            ))
+        }
+
+        fn set(&self, secret_key: &str) {
+            *self.0.lock().unwrap() = Some(Self::keys(secret_key));
+        }
+
+        fn break_down(&self) {
+            *self.0.lock().unwrap() = None;
+        }
+
+        fn reads(&self) -> usize {
+            self.1.load(Ordering::SeqCst)
+        }
+    }
+
+    impl SecretProvider for Rotating {
+        fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            match &*self.0.lock().unwrap() {
+                Some(provider) => provider.secret(lender, kind),
+                None => Err(SupremoError::Config("vault is unreachable".to_string())),
+            }
+        }
+
+        fn location(&self, lender: &str, kind: KeyKind) -> String {
+            format!("vault {} {}", lender, kind)
+        }
+    }
+
+    #[test]
+    fn previous_credentials_expire_after_grace_period() {
+        let store = CredentialStore::new(Credentials::new("public_key", "old"))
+            .with_grace_period(Duration::from_millis(20));
+        store.rotate(Credentials::new("public_key", "new"));
+        assert_eq!(store.previous().unwrap().secret_key.expose(), "old");
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(store.previous().is_none());
+
+        // rotating to the same keys keeps the previous ones
+        assert!(store.rotate(Credentials::new("public_key", "newer")));
+        assert!(!store.rotate(Credentials::new("public_key", "newer")));
+        assert_eq!(store.previous().unwrap().secret_key.expose(), "new");
+        store.retire_previous();
+        assert!(store.previous().is_none());
+    }
+
+    #[test]
+    fn provider_is_read_again_when_due() {
+        let provider = Rotating::new("old");
+        let store = CredentialStore::from_provider("bank", provider.clone())
+            .unwrap()
+            .reload_every(Duration::from_millis(20));
+        assert_eq!(store.current().secret_key.expose(), "old");
+
+        provider.set("new");
+        assert_eq!(store.current().secret_key.expose(), "old");
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(store.current().secret_key.expose(), "new");
+        assert_eq!(store.previous().unwrap().secret_key.expose(), "old");
+        assert!(!store.reload().unwrap());
+    }
+
+    #[test]
+    fn failed_reloads_wait_for_the_next_interval() {
+        let provider = Rotating::new("old");
+        let store = CredentialStore::from_provider("bank", provider.clone())
+            .unwrap()
+            .reload_every(Duration::from_millis(20));
+
+        provider.break_down();
+        std::thread::sleep(Duration::from_millis(30));
+        let reads = provider.reads();
+        assert_eq!(store.current().secret_key.expose(), "old");
+        assert_eq!(provider.reads(), reads + 1);
+        assert!(store
+            .reload_error()
+            .unwrap()
+            .contains("vault is unreachable"));
+
+        // not read again until the interval passed
+        store.current();
+        assert_eq!(provider.reads(), reads + 1);
+
+        provider.set("new");
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(store.current().secret_key.expose(), "new");
+        assert!(store.reload_error().is_none());
+    }
+
+    #[test]
+    fn concurrent_callers_reload_once() {
+        /// provider taking a while to answer, as a remote vault
+        #[derive(Debug)]
+        struct Slow(Rotating);
+
+        impl SecretProvider for Slow {
+            fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+                std::thread::sleep(Duration::from_millis(20));
+                self.0.secret(lender, kind)
+            }
+
+            fn location(&self, lender: &str, kind: KeyKind) -> String {
+                self.0.location(lender, kind)
+            }
+        }
+
+        let provider = Rotating::new("old");
+        let store = CredentialStore::from_provider("bank", Slow(provider.clone()))
+            .unwrap()
+            .reload_every(Duration::from_millis(50));
+        std::thread::sleep(Duration::from_millis(60));
+        let reads = provider.reads();
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || store.current())
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(caller.join().unwrap().secret_key.expose(), "old");
+        }
+        // one public and one secret key read
+        assert_eq!(provider.reads(), reads + 2);
+    }
+}
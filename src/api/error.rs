@@ -0,0 +1,498 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+/// Error returned by every [`LoanClient`](super::client::LoanClient) operation.
+///
+/// Failures keep the raw [`StatusCode`] and the lender's JSON payload so callers can
+/// branch on them without parsing the message string.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::error::SupremoError;
+/// use reqwest::StatusCode;
+///
+/// let err = SupremoError::from_status(
+///     StatusCode::UNAUTHORIZED,
+///     serde_json::json!({"error": "invalid_token"}),
+/// );
+/// assert!(err.is_auth_expired());
+/// assert_eq!(err.status(), Some(StatusCode::UNAUTHORIZED));
+/// ```
+#[derive(Debug)]
+pub enum SupremoError {
+    /// the request could not be sent or the connection failed
+    Transport(reqwest::Error),
+    /// the request timed out before the lender answered
+    Timeout(reqwest::Error),
+    /// the lender answered with a non-success status
+    Http {
+        status: StatusCode,
+        error: Box<ApiError>,
+    },
+    /// the lender rejected the bearer token or client credentials (401)
+    AuthExpired {
+        status: StatusCode,
+        error: Box<ApiError>,
+    },
+    /// the lender answered with a body that does not match the expected type
+    Decode {
+        status: StatusCode,
+        source: serde_json::Error,
+    },
+    /// the input was rejected before any request was made
+    Validation(String),
+    /// the authorization callback's `state` is missing or does not match
+    InvalidState(String),
+    /// an application with the same idempotency key is still waiting for the lender
+    DuplicateRequest { idempotency_key: String },
+    /// the client is misconfigured
+    Config(String),
+}
+
+pub type Result<T> = std::result::Result<T, SupremoError>;
+
+impl SupremoError {
+    /// http status returned by the lender, if a response was received
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            SupremoError::Http { status, .. }
+            | SupremoError::AuthExpired { status, .. }
+            | SupremoError::Decode { status, .. } => Some(*status),
+            SupremoError::Transport(e) | SupremoError::Timeout(e) => e.status(),
+            SupremoError::Validation(_)
+            | SupremoError::InvalidState(_)
+            | SupremoError::DuplicateRequest { .. }
+            | SupremoError::Config(_) => None,
+        }
+    }
+
+    /// json payload returned by the lender with a non-success status
+    pub fn body(&self) -> Option<&serde_json::Value> {
+        self.api_error().map(|error| &error.raw)
+    }
+
+    /// structured lender error returned with a non-success status
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            SupremoError::Http { error, .. } | SupremoError::AuthExpired { error, .. } => {
+                Some(error)
+            }
+            _ => None,
+        }
+    }
+
+    /// semantic kind of the lender error, if the lender answered with one
+    pub fn kind(&self) -> Option<ApiErrorKind> {
+        self.api_error().map(|error| error.kind)
+    }
+
+    pub fn is_auth_expired(&self) -> bool {
+        matches!(self, SupremoError::AuthExpired { .. })
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, SupremoError::Timeout(_))
+    }
+
+    /// delay the lender asked for before the request is sent again
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.api_error().and_then(|error| error.retry_after)
+    }
+
+    /// whether the same request may succeed if sent again later
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SupremoError::Transport(e) => e.is_connect() || e.is_request(),
+            SupremoError::Timeout(_) => true,
+            SupremoError::Http { error, .. } => error.kind.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// build the error matching a non-success response
+    pub fn from_status(status: StatusCode, body: serde_json::Value) -> Self {
+        Self::from_api_error(status, ApiError::from_body(status, body))
+    }
+
+    pub(crate) fn from_api_error(status: StatusCode, error: ApiError) -> Self {
+        let error = Box::new(error);
+        match status {
+            StatusCode::UNAUTHORIZED => SupremoError::AuthExpired { status, error },
+            _ => SupremoError::Http { status, error },
+        }
+    }
+}
+
+impl fmt::Display for SupremoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SupremoError::Transport(e) => write!(f, "transport error: {}", e),
+            SupremoError::Timeout(e) => write!(f, "request timed out: {}", e),
+            SupremoError::Http { status, error } => {
+                write!(f, "lender responded with {}: {}", status, error)
+            }
+            SupremoError::AuthExpired { status, error } => {
+                write!(f, "authorization rejected ({}): {}", status, error)
+            }
+            SupremoError::Decode { status, source } => {
+                write!(f, "could not decode {} response: {}", status, source)
+            }
+            SupremoError::Validation(message) => write!(f, "invalid input: {}", message),
+            SupremoError::InvalidState(message) => {
+                write!(f, "invalid authorization state: {}", message)
+            }
+            SupremoError::DuplicateRequest { idempotency_key } => write!(
+                f,
+                "application with idempotency key {} is already in flight",
+                idempotency_key
+            ),
+            SupremoError::Config(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SupremoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SupremoError::Transport(e) | SupremoError::Timeout(e) => Some(e),
+            SupremoError::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SupremoError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            SupremoError::Timeout(e)
+        } else {
+            SupremoError::Transport(e)
+        }
+    }
+}
+
+/// Semantic meaning of a lender error, derived from its code or status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiErrorKind {
+    /// the loan amount is above the client's remaining limit
+    InsufficientLimit,
+    /// the anchor is not linked to the client
+    AnchorNotLinked,
+    /// the loan term is not offered by the lender
+    InvalidLoanTerm,
+    /// the authorization code or refresh token is invalid, expired or already used
+    InvalidGrant,
+    /// the public/secret key pair was rejected
+    InvalidClient,
+    /// the bearer token is invalid or expired
+    InvalidToken,
+    /// one or more fields of the request were rejected
+    InvalidRequest,
+    /// the resource does not exist
+    NotFound,
+    /// too many requests, try again later
+    RateLimited,
+    /// the lender failed to process the request, try again later
+    ServerError,
+    Unknown,
+}
+
+/// Known lender error codes and the kind they map to.
+const ERROR_CODES: &[(&str, ApiErrorKind)] = &[
+    ("insufficient_limit", ApiErrorKind::InsufficientLimit),
+    ("limit_exceeded", ApiErrorKind::InsufficientLimit),
+    ("max_loan_amount_exceeded", ApiErrorKind::InsufficientLimit),
+    ("anchor_not_linked", ApiErrorKind::AnchorNotLinked),
+    ("anchor_not_found", ApiErrorKind::AnchorNotLinked),
+    ("invalid_anchor", ApiErrorKind::AnchorNotLinked),
+    ("invalid_loan_term", ApiErrorKind::InvalidLoanTerm),
+    ("loan_term_not_supported", ApiErrorKind::InvalidLoanTerm),
+    ("invalid_grant", ApiErrorKind::InvalidGrant),
+    ("invalid_client", ApiErrorKind::InvalidClient),
+    ("unauthorized_client", ApiErrorKind::InvalidClient),
+    ("invalid_token", ApiErrorKind::InvalidToken),
+    ("token_expired", ApiErrorKind::InvalidToken),
+    ("invalid_request", ApiErrorKind::InvalidRequest),
+    ("validation_error", ApiErrorKind::InvalidRequest),
+    ("not_found", ApiErrorKind::NotFound),
+    ("rate_limited", ApiErrorKind::RateLimited),
+    ("too_many_requests", ApiErrorKind::RateLimited),
+    ("server_error", ApiErrorKind::ServerError),
+    ("temporarily_unavailable", ApiErrorKind::ServerError),
+];
+
+impl ApiErrorKind {
+    /// map a lender error code, falling back to the http status for unknown codes
+    pub fn from_code(code: Option<&str>, status: StatusCode) -> Self {
+        let known = code.and_then(|code| {
+            let code = code.to_lowercase();
+            ERROR_CODES
+                .iter()
+                .find(|(known, _)| *known == code)
+                .map(|(_, kind)| *kind)
+        });
+        if let Some(kind) = known {
+            return kind;
+        }
+        match status {
+            StatusCode::UNAUTHORIZED => ApiErrorKind::InvalidToken,
+            StatusCode::NOT_FOUND => ApiErrorKind::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ApiErrorKind::RateLimited,
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                ApiErrorKind::InvalidRequest
+            }
+            status if status.is_server_error() => ApiErrorKind::ServerError,
+            _ => ApiErrorKind::Unknown,
+        }
+    }
+
+    /// transient failures may succeed when retried, the rest will fail again
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ApiErrorKind::RateLimited | ApiErrorKind::ServerError)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Error payload returned by the lender with a non-success status.
+///
+/// Lenders do not all use the same field names, so the common spellings
+/// (`code`/`error`, `message`/`error_description`/`detail`, `errors`) are accepted.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::error::{ApiError, ApiErrorKind};
+/// use reqwest::StatusCode;
+///
+/// let error = ApiError::from_body(
+///     StatusCode::BAD_REQUEST,
+///     serde_json::json!({
+///         "code": "insufficient_limit",
+///         "message": "amount is above remaining limit",
+///         "errors": {"amount": ["must be at most 5000"]},
+///         "request_id": "req-1"
+///     }),
+/// );
+/// assert_eq!(error.kind, ApiErrorKind::InsufficientLimit);
+/// assert_eq!(error.field_errors[0].field, "amount");
+/// assert_eq!(error.request_id.as_deref(), Some("req-1"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub kind: ApiErrorKind,
+    pub code: Option<String>,
+    pub message: Option<String>,
+    pub field_errors: Vec<FieldError>,
+    pub request_id: Option<String>,
+    /// delay requested by the lender through the `Retry-After` header
+    #[serde(default)]
+    pub retry_after: Option<Duration>,
+    /// the payload exactly as returned by the lender
+    pub raw: serde_json::Value,
+}
+
+impl ApiError {
+    pub fn from_body(status: StatusCode, raw: serde_json::Value) -> Self {
+        let code = first_string(&raw, &["code", "error_code", "error"]);
+        let message = first_string(&raw, &["message", "error_description", "detail", "msg"]);
+        let request_id = first_string(&raw, &["request_id", "requestId", "trace_id"]);
+        let field_errors = raw
+            .get("errors")
+            .or_else(|| raw.get("field_errors"))
+            .map(parse_field_errors)
+            .unwrap_or_default();
+        let kind = ApiErrorKind::from_code(code.as_deref(), status);
+
+        ApiError {
+            kind,
+            code,
+            message,
+            field_errors,
+            request_id,
+            retry_after: None,
+            raw,
+        }
+    }
+
+    /// like [`ApiError::from_body`], falling back to the `x-request-id` header and
+    /// reading `Retry-After`
+    pub(crate) fn from_response(
+        status: StatusCode,
+        headers: &HeaderMap,
+        raw: serde_json::Value,
+    ) -> Self {
+        let mut error = Self::from_body(status, raw);
+        if error.request_id.is_none() {
+            error.request_id = headers
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+        }
+        error.retry_after = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        error
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(
+        at.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.code, &self.message) {
+            (Some(code), Some(message)) => write!(f, "{}: {}", code, message)?,
+            (Some(text), None) | (None, Some(text)) => write!(f, "{}", text)?,
+            (None, None) => write!(f, "{}", self.raw)?,
+        }
+        for field_error in &self.field_errors {
+            write!(f, "; {}: {}", field_error.field, field_error.message)?;
+        }
+        Ok(())
+    }
+}
+
+fn first_string(raw: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| raw.get(key).and_then(|value| value.as_str()))
+        .map(str::to_string)
+}
+
+/// accepts `{"field": ["message"]}`, `{"field": "message"}` and
+/// `[{"field": "field", "message": "message"}]`
+fn parse_field_errors(errors: &serde_json::Value) -> Vec<FieldError> {
+    match errors {
+        serde_json::Value::Object(fields) => fields
+            .iter()
+            .flat_map(|(field, messages)| {
+                let messages = match messages {
+                    serde_json::Value::Array(messages) => messages
+                        .iter()
+                        .map(|message| {
+                            message
+                                .as_str()
+                                .map(str::to_string)
+                                .unwrap_or_else(|| message.to_string())
+                        })
+                        .collect(),
+                    serde_json::Value::String(message) => vec![message.clone()],
+                    other => vec![other.to_string()],
+                };
+                messages.into_iter().map(move |message| FieldError {
+                    field: field.clone(),
+                    message,
+                })
+            })
+            .collect(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|item| {
+                Some(FieldError {
+                    field: first_string(item, &["field", "loc", "name"])?,
+                    message: first_string(item, &["message", "msg", "detail"]).unwrap_or_default(),
+                })
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unauthorized_maps_to_auth_expired() {
+        let err = SupremoError::from_status(
+            StatusCode::UNAUTHORIZED,
+            serde_json::json!({"error": "invalid_token"}),
+        );
+        assert!(err.is_auth_expired());
+        assert_eq!(err.body().unwrap()["error"], "invalid_token");
+    }
+
+    #[test]
+    fn other_status_keeps_payload() {
+        let err = SupremoError::from_status(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({"message": "limit exceeded"}),
+        );
+        assert!(!err.is_auth_expired());
+        assert_eq!(err.status(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(err.body().unwrap()["message"], "limit exceeded");
+    }
+    #[test]
+    fn known_codes_map_to_kinds() {
+        let error = ApiError::from_body(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({"error": "invalid_grant", "error_description": "code already used"}),
+        );
+        assert_eq!(error.kind, ApiErrorKind::InvalidGrant);
+        assert_eq!(error.message.as_deref(), Some("code already used"));
+        assert!(!error.kind.is_transient());
+
+        let error = ApiError::from_body(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            serde_json::json!({"code": "ANCHOR_NOT_LINKED"}),
+        );
+        assert_eq!(error.kind, ApiErrorKind::AnchorNotLinked);
+    }
+
+    #[test]
+    fn unknown_codes_fall_back_to_status() {
+        let error = ApiError::from_body(StatusCode::BAD_GATEWAY, serde_json::json!("bad gateway"));
+        assert_eq!(error.kind, ApiErrorKind::ServerError);
+        assert!(SupremoError::from_api_error(StatusCode::BAD_GATEWAY, error).is_transient());
+    }
+
+    #[test]
+    fn field_errors_from_list() {
+        let error = ApiError::from_body(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({"errors": [{"field": "loan_term", "message": "must be 30 or 60"}]}),
+        );
+        assert_eq!(
+            error.field_errors,
+            vec![FieldError {
+                field: "loan_term".to_string(),
+                message: "must be 30 or 60".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::RETRY_AFTER, "3".parse().unwrap());
+        let error = ApiError::from_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            serde_json::Value::Null,
+        );
+        assert_eq!(error.kind, ApiErrorKind::RateLimited);
+        assert_eq!(error.retry_after, Some(Duration::from_secs(3)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,848 @@
+//! In-memory lender for tests, available with the `test-support` feature.
+//!
+//! [`FakeLender`] implements [`LoanApi`] on top of local state (clients and their limits,
+//! anchors, a fee schedule and issued tokens) and applies the same checks as a lender
+//! would, so code written against [`LoanApi`] can be exercised without any network.
+//! Failures can be scripted per endpoint with [`FakeLender::fail_next`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde_json::json;
+
+use super::client::validate_loan_inputs;
+use super::error::{ApiError, Result, SupremoError};
+use super::loan_api::LoanApi;
+use super::retry::Endpoint;
+use super::types::{
+    Anchor, AnchorPagination, CalculateLonaResponse, ClientLimit, LoanCreted, LoanInput, OuathCode,
+    OuathToken, OuathUser, PaginatedAnchors, TokenIntrospection, UserSession, UserToken,
+};
+
+/// Fees charged by a [`FakeLender`], rates are fractions of the loan amount.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeSchedule {
+    /// interest per 30 days of loan term
+    pub monthly_interest_rate: f64,
+    pub processing_fee_rate: f64,
+    pub facility_fee_rate: f64,
+    pub insurance_rate: f64,
+    /// charged on the processing and facility fees
+    pub excise_duty_rate: f64,
+    /// longest accepted loan term in days
+    pub max_loan_term: i32,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule {
+            monthly_interest_rate: 0.04,
+            processing_fee_rate: 0.01,
+            facility_fee_rate: 0.005,
+            insurance_rate: 0.002,
+            excise_duty_rate: 0.2,
+            max_loan_term: 90,
+        }
+    }
+}
+
+impl FeeSchedule {
+    /// charges for one loan, as returned by the calc-loan endpoint
+    pub fn charges(&self, input: &LoanInput) -> CalculateLonaResponse {
+        let amount = input.amount;
+        let interest_amount = amount * self.monthly_interest_rate * input.loan_term as f64 / 30.0;
+        let processing_fee = amount * self.processing_fee_rate;
+        let facility_fee = amount * self.facility_fee_rate;
+        let insurance = amount * self.insurance_rate;
+        let excise_duty = (processing_fee + facility_fee) * self.excise_duty_rate;
+        CalculateLonaResponse {
+            excise_duty,
+            facility_fee,
+            insurance,
+            interest_amount,
+            oauth_apply: input.clone(),
+            processing_fee,
+            total: amount
+                + interest_amount
+                + processing_fee
+                + facility_fee
+                + insurance
+                + excise_duty,
+        }
+    }
+}
+
+/// Scripted failure returned by a [`FakeLender`] endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// `401` with `token_expired`
+    ExpiredToken,
+    /// `400` with `insufficient_limit`
+    InsufficientLimit,
+    /// `500` with `server_error`
+    ServerError,
+    /// `429` with `rate_limited` and the given `Retry-After`
+    RateLimited(Option<Duration>),
+    /// any status and error code
+    Status { status: StatusCode, code: String },
+}
+
+impl Failure {
+    fn into_error(self) -> SupremoError {
+        let (status, code, retry_after) = match self {
+            Failure::ExpiredToken => (StatusCode::UNAUTHORIZED, "token_expired".to_string(), None),
+            Failure::InsufficientLimit => (
+                StatusCode::BAD_REQUEST,
+                "insufficient_limit".to_string(),
+                None,
+            ),
+            Failure::ServerError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error".to_string(),
+                None,
+            ),
+            Failure::RateLimited(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited".to_string(),
+                retry_after,
+            ),
+            Failure::Status { status, code } => (status, code, None),
+        };
+        let mut error = ApiError::from_body(
+            status,
+            json!({"code": code, "message": "injected by FakeLender"}),
+        );
+        error.retry_after = retry_after;
+        SupremoError::from_api_error(status, error)
+    }
+}
+
+/// the error a lender returns for `code`
+fn lender_error(status: StatusCode, code: &str, message: String) -> SupremoError {
+    SupremoError::from_status(status, json!({"code": code, "message": message}))
+}
+
+#[derive(Debug)]
+struct Client {
+    total_limit: f64,
+    used_limit: f64,
+    anchors: Vec<i32>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    clients: HashMap<i32, Client>,
+    anchors: HashMap<i32, Anchor>,
+    codes: HashMap<String, OuathUser>,
+    /// borrower profiles by user id
+    users: HashMap<i32, OuathUser>,
+    tokens: HashSet<String>,
+    /// user access and refresh tokens with the borrower they were issued to
+    sessions: HashMap<String, i32>,
+    refresh_tokens: HashSet<String>,
+    keep_refresh_tokens: bool,
+    issued: usize,
+    fees: FeeSchedule,
+    failures: HashMap<Endpoint, VecDeque<Failure>>,
+    calls: HashMap<Endpoint, usize>,
+    applications: Vec<LoanInput>,
+    latency: Duration,
+}
+
+impl State {
+    /// count the call and return the next scripted failure for `endpoint`
+    fn enter(&mut self, endpoint: Endpoint) -> Result<()> {
+        *self.calls.entry(endpoint).or_default() += 1;
+        match self
+            .failures
+            .get_mut(&endpoint)
+            .and_then(VecDeque::pop_front)
+        {
+            Some(failure) => Err(failure.into_error()),
+            None => Ok(()),
+        }
+    }
+
+    fn issue_token(&mut self) -> String {
+        self.issued += 1;
+        let token = format!("fake-token-{}", self.issued);
+        self.tokens.insert(token.clone());
+        token
+    }
+
+    /// access token and refresh token for `user_id`, the refresh token is only accepted
+    /// by the `refresh_token` grant
+    fn issue_session(&mut self, user_id: i32) -> UserToken {
+        let access_token = self.issue_token();
+        self.issued += 1;
+        let refresh_token = format!("fake-refresh-{}", self.issued);
+        self.refresh_tokens.insert(refresh_token.clone());
+        self.sessions.insert(access_token.clone(), user_id);
+        self.sessions.insert(refresh_token.clone(), user_id);
+        UserToken::from_code(OuathCode {
+            access_token: access_token.into(),
+            refresh_token: refresh_token.into(),
+            token_type: "Bearer".to_string(),
+            expires_in: 3600,
+            scope: "read".to_string(),
+        })
+    }
+
+    /// `None` stands for a token managed by the caller, which is always valid
+    fn authorize(&self, bearer_token: Option<&str>) -> Result<()> {
+        match bearer_token {
+            Some(token) if !self.tokens.contains(token) => Err(lender_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "the access token is invalid or expired".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn client(&self, client_id: i32) -> Result<&Client> {
+        self.clients.get(&client_id).ok_or_else(|| {
+            lender_error(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                format!("client {} does not exist", client_id),
+            )
+        })
+    }
+
+    /// check a batch the way the lender does, counting earlier loans in the same batch
+    fn check_batch(&self, body: &[LoanInput]) -> Result<()> {
+        let mut client_requested: HashMap<i32, f64> = HashMap::new();
+        let mut anchor_requested: HashMap<i32, f64> = HashMap::new();
+        for input in body {
+            let client = self.client(input.client_id)?;
+            let anchor = match self.anchors.get(&input.anchor_id) {
+                Some(anchor) if client.anchors.contains(&input.anchor_id) => anchor,
+                _ => {
+                    return Err(lender_error(
+                        StatusCode::BAD_REQUEST,
+                        "anchor_not_linked",
+                        format!(
+                            "anchor {} is not linked to client {}",
+                            input.anchor_id, input.client_id
+                        ),
+                    ))
+                }
+            };
+            if input.loan_term < 1 || input.loan_term > self.fees.max_loan_term {
+                return Err(lender_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_loan_term",
+                    format!(
+                        "loan term must be between 1 and {} days",
+                        self.fees.max_loan_term
+                    ),
+                ));
+            }
+
+            let client_total =
+                client_requested.get(&input.client_id).unwrap_or(&0.0) + input.amount;
+            let anchor_total =
+                anchor_requested.get(&input.anchor_id).unwrap_or(&0.0) + input.amount;
+            if client.used_limit + client_total > client.total_limit
+                || anchor.loaned_amount + anchor_total > anchor.max_loan_amount
+            {
+                return Err(lender_error(
+                    StatusCode::BAD_REQUEST,
+                    "insufficient_limit",
+                    format!("{} exceeds the remaining limit", input.amount),
+                ));
+            }
+            client_requested.insert(input.client_id, client_total);
+            anchor_requested.insert(input.anchor_id, anchor_total);
+        }
+        Ok(())
+    }
+}
+
+/// Lender kept in memory, for tests of code using [`LoanApi`].
+///
+/// Clones share the same state, so a test can keep a handle to inspect calls and
+/// script failures while the code under test owns another.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::fake::{anchor, Failure, FakeLender};
+/// use supremo_loan::api::loan_api::LoanApi;
+/// use supremo_loan::api::retry::Endpoint;
+/// use supremo_loan::api::types::LoanInput;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let lender = FakeLender::new()
+///     .with_client(7, 10_000.0)
+///     .with_anchor(7, anchor(3, 5_000.0));
+/// let loan = LoanInput {
+///     amount: 1_000.0,
+///     anchor_id: 3,
+///     client_id: 7,
+///     loan_term: 30,
+///     loan_type: "api_request".to_string(),
+///     metadata: serde_json::json!({}),
+/// };
+///
+/// lender.fail_next(Endpoint::ApplyLoan, Failure::ServerError);
+/// assert!(lender.apply_for_loan(None, vec![loan.clone()]).await.is_err());
+/// lender.apply_for_loan(None, vec![loan]).await.unwrap();
+/// assert_eq!(lender.client_limit(None, 7).await.unwrap().used_limit, 1_000.0);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FakeLender {
+    state: Arc<Mutex<State>>,
+    /// the caller waits out the latency on its own timer
+    caller_waits: bool,
+}
+
+/// anchor record with `max_loan_amount` and nothing loaned yet
+pub fn anchor(anchor_id: i32, max_loan_amount: f64) -> Anchor {
+    Anchor {
+        anchor_id,
+        business_logo: None,
+        business_type: None,
+        company_email: Some(format!("anchor-{}@example.com", anchor_id)),
+        company_name: Some(format!("Anchor {}", anchor_id)),
+        created_at: "2023-01-01T00:00:00Z".to_string(),
+        loaned_amount: 0.0,
+        max_loan_amount,
+        tener_id: 1,
+        updated_at: "2023-01-01T00:00:00Z".to_string(),
+    }
+}
+
+impl FakeLender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register a client with its total limit
+    pub fn with_client(self, client_id: i32, total_limit: f64) -> Self {
+        self.state().clients.insert(
+            client_id,
+            Client {
+                total_limit,
+                used_limit: 0.0,
+                anchors: Vec::new(),
+            },
+        );
+        self
+    }
+
+    /// add `anchor` and link it to `client_id`, which must have been registered
+    pub fn with_anchor(self, client_id: i32, anchor: Anchor) -> Self {
+        {
+            let mut state = self.state();
+            let client = state
+                .clients
+                .get_mut(&client_id)
+                .expect("with_client must be called before with_anchor");
+            if !client.anchors.contains(&anchor.anchor_id) {
+                client.anchors.push(anchor.anchor_id);
+            }
+            state.anchors.insert(anchor.anchor_id, anchor);
+        }
+        self
+    }
+
+    /// authorization code that [`LoanApi::exchange_code_auth`] accepts once for `user`
+    pub fn with_user(self, code: impl Into<String>, user: OuathUser) -> Self {
+        {
+            let mut state = self.state();
+            state.users.insert(user.id, user.clone());
+            state.codes.insert(code.into(), user);
+        }
+        self
+    }
+
+    /// answer the `refresh_token` grant without a new refresh token, as lenders that
+    /// do not rotate them
+    pub fn keep_refresh_tokens(self) -> Self {
+        self.state().keep_refresh_tokens = true;
+        self
+    }
+
+    /// replace the profile of the borrower with the same id, e.g. moved to another anchor
+    pub fn update_user(&self, user: OuathUser) {
+        self.state().users.insert(user.id, user);
+    }
+
+    pub fn with_fees(self, fees: FeeSchedule) -> Self {
+        self.state().fees = fees;
+        self
+    }
+
+    /// answer every call after `latency`, to exercise deadlines
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.state().latency = latency;
+        self
+    }
+
+    pub(crate) fn latency(&self) -> Duration {
+        self.state().latency
+    }
+
+    /// same lender, but calls answer right away and leave the latency to the caller,
+    /// e.g. a `MockServer` on the actix runtime where the tokio 1 timer is not available
+    #[cfg(feature = "actix")]
+    pub(crate) fn without_latency(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            caller_waits: true,
+        }
+    }
+
+    async fn wait(&self) {
+        let latency = self.latency();
+        if !self.caller_waits && !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+    }
+
+    /// make the next call to `endpoint` fail, calls queue up in order
+    pub fn fail_next(&self, endpoint: Endpoint, failure: Failure) {
+        self.state()
+            .failures
+            .entry(endpoint)
+            .or_default()
+            .push_back(failure);
+    }
+
+    /// make the next `times` calls to `endpoint` fail
+    pub fn fail_times(&self, endpoint: Endpoint, times: usize, failure: Failure) {
+        for _ in 0..times {
+            self.fail_next(endpoint, failure.clone());
+        }
+    }
+
+    /// new access token for the borrower of `refresh_token`, which is replaced by a new
+    /// one unless [`FakeLender::keep_refresh_tokens`] was called
+    pub async fn refresh_token(&self, refresh_token: &str) -> Result<UserToken> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::RefreshToken)?;
+        let user_id = match state.sessions.get(refresh_token) {
+            Some(user_id) if state.refresh_tokens.contains(refresh_token) => *user_id,
+            _ => {
+                return Err(lender_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_grant",
+                    "the refresh token is invalid or was revoked".to_string(),
+                ))
+            }
+        };
+        let mut token = state.issue_session(user_id);
+        if state.keep_refresh_tokens {
+            let issued = token.refresh_token.expose().to_string();
+            state.refresh_tokens.remove(&issued);
+            state.sessions.remove(&issued);
+            token.refresh_token = refresh_token.into();
+        } else {
+            state.refresh_tokens.remove(refresh_token);
+            state.sessions.remove(refresh_token);
+        }
+        Ok(token)
+    }
+
+    /// profile of the borrower an access token was issued to
+    pub async fn user_info(&self, access_token: &str) -> Result<OuathUser> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::UserInfo)?;
+        state.authorize(Some(access_token))?;
+        state
+            .sessions
+            .get(access_token)
+            .and_then(|user_id| state.users.get(user_id))
+            .cloned()
+            .ok_or_else(|| {
+                lender_error(
+                    StatusCode::UNAUTHORIZED,
+                    "invalid_token",
+                    "the access token was not issued to a borrower".to_string(),
+                )
+            })
+    }
+
+    /// revoke an access or refresh token, unknown tokens are ignored as in RFC 7009
+    pub async fn revoke_token(&self, token: &str) -> Result<()> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::RevokeToken)?;
+        state.tokens.remove(token);
+        state.refresh_tokens.remove(token);
+        state.sessions.remove(token);
+        Ok(())
+    }
+
+    /// whether a token is active, with the borrower it was issued to
+    pub async fn introspect_token(&self, token: &str) -> Result<TokenIntrospection> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::IntrospectToken)?;
+        let refresh = state.refresh_tokens.contains(token);
+        if !refresh && !state.tokens.contains(token) {
+            return Ok(TokenIntrospection::default());
+        }
+        let sub = state.sessions.get(token).map(i32::to_string);
+        Ok(TokenIntrospection {
+            active: true,
+            scope: Some(if sub.is_some() { "read" } else { "read write" }.to_string()),
+            token_type: Some(if refresh { "refresh_token" } else { "Bearer" }.to_string()),
+            sub,
+            ..TokenIntrospection::default()
+        })
+    }
+
+    /// expire every token issued so far, later calls using them get a `401`
+    pub fn expire_tokens(&self) {
+        self.state().tokens.clear();
+    }
+
+    /// number of calls made to `endpoint`, including failed ones
+    pub fn calls(&self, endpoint: Endpoint) -> usize {
+        self.state().calls.get(&endpoint).copied().unwrap_or(0)
+    }
+
+    /// loans accepted by `apply_for_loan`, in order
+    pub fn applications(&self) -> Vec<LoanInput> {
+        self.state().applications.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl LoanApi for FakeLender {
+    async fn get_auth_token(&self) -> Result<OuathToken> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::AuthToken)?;
+        Ok(OuathToken {
+            access_token: state.issue_token().into(),
+            token_type: "Bearer".to_string(),
+            expires_in: 3600,
+            scope: "read write".to_string(),
+        })
+    }
+
+    async fn exchange_code_auth(&self, code: &str) -> Result<UserSession> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::ExchangeCode)?;
+        let user = state.codes.remove(code).ok_or_else(|| {
+            lender_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "the authorization code is invalid or was already used".to_string(),
+            )
+        })?;
+        let token = state.issue_session(user.id);
+        Ok(UserSession { user, token })
+    }
+
+    async fn client_limit(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+    ) -> Result<ClientLimit> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::ClientLimit)?;
+        state.authorize(bearer_token)?;
+        let client = state.client(client_id)?;
+        Ok(ClientLimit {
+            remaining_limit: client.total_limit - client.used_limit,
+            total_limit: client.total_limit,
+            used_limit: client.used_limit,
+        })
+    }
+
+    async fn get_anchors(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+        pagination: Option<AnchorPagination>,
+    ) -> Result<PaginatedAnchors> {
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::Anchors)?;
+        state.authorize(bearer_token)?;
+        let client = state.client(client_id)?;
+
+        let pagination = pagination.unwrap_or_default();
+        let page = pagination.page.unwrap_or(1).max(1);
+        let page_size = pagination.page_size.unwrap_or(10).clamp(1, 100);
+        let mut anchors: Vec<Anchor> = client
+            .anchors
+            .iter()
+            .filter_map(|id| state.anchors.get(id).cloned())
+            .collect();
+        anchors.sort_by_key(|anchor| anchor.anchor_id);
+        if pagination.order.as_deref() != Some("id") {
+            anchors.reverse();
+        }
+
+        let total = anchors.len() as i32;
+        let data = anchors
+            .into_iter()
+            .skip((page - 1).saturating_mul(page_size) as usize)
+            .take(page_size as usize)
+            .collect();
+        Ok(PaginatedAnchors {
+            data,
+            page,
+            page_size,
+            total,
+        })
+    }
+
+    async fn calculate_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<Vec<CalculateLonaResponse>> {
+        // the client refuses these before sending them
+        validate_loan_inputs(&body)?;
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::CalculateLoan)?;
+        state.authorize(bearer_token)?;
+        state.check_batch(&body)?;
+        Ok(body.iter().map(|input| state.fees.charges(input)).collect())
+    }
+
+    async fn apply_for_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<LoanCreted> {
+        // the client refuses these before sending them
+        validate_loan_inputs(&body)?;
+        self.wait().await;
+        let mut state = self.state();
+        state.enter(Endpoint::ApplyLoan)?;
+        state.authorize(bearer_token)?;
+        state.check_batch(&body)?;
+        for input in &body {
+            if let Some(client) = state.clients.get_mut(&input.client_id) {
+                client.used_limit += input.amount;
+            }
+            if let Some(anchor) = state.anchors.get_mut(&input.anchor_id) {
+                anchor.loaned_amount += input.amount;
+            }
+        }
+        state.applications.extend(body);
+        Ok(LoanCreted {
+            message: "loan created".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::error::ApiErrorKind;
+
+    fn loan(amount: f64, anchor_id: i32) -> LoanInput {
+        LoanInput {
+            amount,
+            anchor_id,
+            client_id: 7,
+            loan_term: 30,
+            loan_type: "api_request".to_string(),
+            metadata: json!({}),
+        }
+    }
+
+    fn lender() -> FakeLender {
+        FakeLender::new()
+            .with_client(7, 10_000.0)
+            .with_anchor(7, anchor(1, 3_000.0))
+            .with_anchor(7, anchor(2, 20_000.0))
+    }
+
+    #[tokio::test]
+    async fn applying_consumes_client_and_anchor_limits() {
+        let lender = lender();
+        let charges = lender.calculate_loan(None, vec![loan(1_000.0, 1)]).await;
+        let charges = charges.unwrap();
+        assert_eq!(charges[0].interest_amount, 40.0);
+        assert!(charges[0].total > 1_040.0);
+
+        lender
+            .apply_for_loan(None, vec![loan(2_500.0, 1)])
+            .await
+            .unwrap();
+        let err = lender
+            .apply_for_loan(None, vec![loan(1_000.0, 1)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(ApiErrorKind::InsufficientLimit));
+
+        // the batch is checked as a whole against the client limit
+        let err = lender
+            .apply_for_loan(None, vec![loan(4_000.0, 2), loan(4_000.0, 2)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(ApiErrorKind::InsufficientLimit));
+
+        let limit = lender.client_limit(None, 7).await.unwrap();
+        assert_eq!(limit.remaining_limit, 7_500.0);
+        assert_eq!(lender.applications().len(), 1);
+        assert_eq!(lender.calls(Endpoint::ApplyLoan), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_anchors_terms_and_tokens() {
+        let lender = lender();
+        let err = lender
+            .calculate_loan(None, vec![loan(10.0, 9)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(ApiErrorKind::AnchorNotLinked));
+
+        let mut long = loan(10.0, 1);
+        long.loan_term = 365;
+        let err = lender.calculate_loan(None, vec![long]).await.unwrap_err();
+        assert_eq!(err.kind(), Some(ApiErrorKind::InvalidLoanTerm));
+
+        let token = lender.get_auth_token().await.unwrap().access_token;
+        assert!(lender.client_limit(Some(token.expose()), 7).await.is_ok());
+        lender.expire_tokens();
+        let err = lender
+            .client_limit(Some(token.expose()), 7)
+            .await
+            .unwrap_err();
+        assert!(err.is_auth_expired());
+    }
+
+    #[tokio::test]
+    async fn rejects_batches_the_client_refuses() {
+        let lender = lender();
+        let err = lender.calculate_loan(None, vec![]).await.unwrap_err();
+        assert!(matches!(err, SupremoError::Validation(_)));
+
+        let mut other_client = loan(10.0, 1);
+        other_client.client_id = 8;
+        let err = lender
+            .apply_for_loan(None, vec![loan(10.0, 1), other_client])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SupremoError::Validation(_)));
+        assert_eq!(lender.calls(Endpoint::ApplyLoan), 0);
+        assert!(lender.applications().is_empty());
+    }
+
+    #[tokio::test]
+    async fn scripted_failures_are_returned_in_order() {
+        let lender = lender();
+        lender.fail_next(Endpoint::ClientLimit, Failure::ExpiredToken);
+        lender.fail_next(
+            Endpoint::ClientLimit,
+            Failure::RateLimited(Some(Duration::from_secs(2))),
+        );
+
+        assert!(lender
+            .client_limit(None, 7)
+            .await
+            .unwrap_err()
+            .is_auth_expired());
+        let err = lender.client_limit(None, 7).await.unwrap_err();
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(2)));
+        assert!(err.is_transient());
+        assert!(lender.client_limit(None, 7).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn codes_are_exchanged_once() {
+        let user = OuathUser {
+            id: 7,
+            email: "borrower@example.com".to_string(),
+            company_name: "Borrower Ltd".to_string(),
+            anchor_id: 1,
+        };
+        let lender = lender().with_user("code-1", user.clone());
+        let session = lender.exchange_code_auth("code-1").await.unwrap();
+        assert_eq!(session.user, user);
+        assert!(lender
+            .client_limit(Some(session.token.access_token.expose()), 7)
+            .await
+            .is_ok());
+        let err = lender.exchange_code_auth("code-1").await.unwrap_err();
+        assert_eq!(err.kind(), Some(ApiErrorKind::InvalidGrant));
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_rotate_until_revoked() {
+        let user = OuathUser {
+            id: 7,
+            email: "borrower@example.com".to_string(),
+            company_name: "Borrower Ltd".to_string(),
+            anchor_id: 1,
+        };
+        let lender = lender().with_user("code-1", user.clone());
+        let session = lender.exchange_code_auth("code-1").await.unwrap();
+        let first = session.token.refresh_token.expose();
+
+        let refreshed = lender.refresh_token(first).await.unwrap();
+        assert_ne!(refreshed.refresh_token.expose(), first);
+        let err = lender.refresh_token(first).await.unwrap_err();
+        assert_eq!(err.kind(), Some(ApiErrorKind::InvalidGrant));
+
+        let access_token = refreshed.access_token.expose();
+        assert_eq!(lender.user_info(access_token).await.unwrap(), user);
+        let introspection = lender.introspect_token(access_token).await.unwrap();
+        assert!(introspection.active);
+        assert_eq!(introspection.sub.as_deref(), Some("7"));
+
+        lender.revoke_token(access_token).await.unwrap();
+        assert!(!lender.introspect_token(access_token).await.unwrap().active);
+        assert!(lender.user_info(access_token).await.is_err());
+        // revoking an unknown token succeeds
+        lender.revoke_token("unknown").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn anchors_are_paginated() {
+        let lender = lender().with_anchor(7, anchor(3, 1_000.0));
+        let page = lender
+            .get_anchors(
+                None,
+                7,
+                Some(AnchorPagination {
+                    page: Some(2),
+                    page_size: Some(2),
+                    order: Some("id".to_string()),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.data[0].anchor_id, 3);
+
+        let past_the_end = lender
+            .get_anchors(
+                None,
+                7,
+                Some(AnchorPagination {
+                    page: Some(i32::MAX),
+                    page_size: Some(100),
+                    order: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert!(past_the_end.data.is_empty());
+    }
+}
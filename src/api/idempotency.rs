@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::error::{Result, SupremoError};
+use super::types::{LoanCreted, LoanInput};
+
+/// header carrying the idempotency key of a loan application
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Key identifying one loan application across retries.
+///
+/// The lender processes at most one application per key, so an application that
+/// timed out can be sent again with the same key without lending twice.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::idempotency::IdempotencyKey;
+/// use supremo_loan::api::types::LoanInput;
+///
+/// let batch = vec![LoanInput {
+///     amount: 1000.0,
+///     anchor_id: 1,
+///     client_id: 1,
+///     loan_term: 30,
+///     loan_type: "api_request".to_string(),
+///     metadata: serde_json::json!({"order_id": "ord-1"}),
+/// }];
+/// // the same batch always produces the same key
+/// assert_eq!(IdempotencyKey::for_batch(&batch), IdempotencyKey::for_batch(&batch));
+/// // random keys for callers that track applications themselves
+/// assert_ne!(IdempotencyKey::generate(), IdempotencyKey::generate());
+/// assert!(IdempotencyKey::new("order-1").is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    /// use a caller supplied key, e.g. the id of the order being financed
+    pub fn new(key: impl Into<String>) -> Result<Self> {
+        let key = key.into();
+        if key.is_empty() || key.len() > 255 {
+            return Err(SupremoError::Validation(
+                "idempotency key must be between 1 and 255 characters".to_string(),
+            ));
+        }
+        if !key.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(SupremoError::Validation(
+                "idempotency key must only contain visible ascii characters".to_string(),
+            ));
+        }
+        Ok(Self(key))
+    }
+
+    /// random key, unique for every call
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(to_hex(&bytes))
+    }
+
+    /// key derived from the content of the batch, so resubmitting the same batch
+    /// (for instance after a restart) reuses the key of the first attempt
+    pub fn for_batch(body: &[LoanInput]) -> Self {
+        let encoded = serde_json::to_vec(body).expect("loan inputs serialize to json");
+        Self(to_hex(&Sha256::digest(encoded)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// State of an application recorded in an [`IdempotencyStore`].
+#[derive(Debug, Clone)]
+pub enum ApplicationState {
+    /// the application was sent and no answer has been received yet
+    InFlight,
+    /// the application may have reached the lender but the answer was lost (timeout,
+    /// transport error or cancelled call), it is sent again with the same key
+    Unknown,
+    /// the lender accepted the application
+    Completed(LoanCreted),
+}
+
+type Entries = HashMap<IdempotencyKey, (ApplicationState, Instant)>;
+
+/// Local record of loan applications by idempotency key.
+///
+/// Clones share the same record, so every clone of a
+/// [`LoanClient`](super::client::LoanClient) sees the same applications.
+/// Applications the lender rejected are forgotten. Applications whose outcome is
+/// unknown are kept and sent again with the same key, leaving it to the lender to
+/// deduplicate.
+#[derive(Debug, Clone)]
+pub struct IdempotencyStore {
+    entries: Arc<Mutex<Entries>>,
+    ttl: Duration,
+    in_flight_ttl: Duration,
+}
+
+impl Default for IdempotencyStore {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(24 * 60 * 60))
+    }
+}
+
+impl IdempotencyStore {
+    /// an application in flight for longer is treated as unknown, so it can be sent again
+    pub const DEFAULT_IN_FLIGHT_TTL: Duration = Duration::from_secs(5 * 60);
+
+    /// keep completed applications for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+            in_flight_ttl: Self::DEFAULT_IN_FLIGHT_TTL,
+        }
+    }
+
+    /// how long an application stays in flight before another call may send it again
+    pub fn with_in_flight_ttl(mut self, in_flight_ttl: Duration) -> Self {
+        self.in_flight_ttl = in_flight_ttl;
+        self
+    }
+
+    pub fn state(&self, key: &IdempotencyKey) -> Option<ApplicationState> {
+        let mut entries = self.entries.lock().unwrap();
+        self.evict_expired(&mut entries);
+        entries.get(key).map(|(state, _)| state.clone())
+    }
+
+    /// mark the application as in flight, returning the stored result if it already completed
+    ///
+    /// the returned guard records the outcome, dropping it unsettled (e.g. when the call
+    /// is cancelled) marks the outcome as unknown
+    pub(crate) fn begin<'a>(&'a self, key: &'a IdempotencyKey) -> Result<Begin<'a>> {
+        let mut entries = self.entries.lock().unwrap();
+        self.evict_expired(&mut entries);
+        match entries.get(key) {
+            Some((ApplicationState::Completed(created), _)) => {
+                return Ok(Begin::Completed(created.clone()))
+            }
+            Some((ApplicationState::InFlight, at)) if at.elapsed() < self.in_flight_ttl => {
+                return Err(SupremoError::DuplicateRequest {
+                    idempotency_key: key.to_string(),
+                })
+            }
+            _ => {}
+        }
+        entries.insert(key.clone(), (ApplicationState::InFlight, Instant::now()));
+        Ok(Begin::Started(PendingApplication {
+            store: self,
+            key,
+            settled: false,
+        }))
+    }
+
+    fn set(&self, key: &IdempotencyKey, state: ApplicationState) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.clone(), (state, Instant::now()));
+    }
+
+    fn abandon(&self, key: &IdempotencyKey) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn evict_expired(&self, entries: &mut Entries) {
+        entries.retain(|_, (state, at)| match state {
+            ApplicationState::InFlight => at.elapsed() < self.ttl.max(self.in_flight_ttl),
+            _ => at.elapsed() < self.ttl,
+        });
+    }
+}
+
+/// Result of [`IdempotencyStore::begin`].
+pub(crate) enum Begin<'a> {
+    /// the application already completed
+    Completed(LoanCreted),
+    /// the application is in flight until the guard is settled or dropped
+    Started(PendingApplication<'a>),
+}
+
+/// An application in flight, marked as unknown when dropped unsettled.
+#[must_use]
+pub(crate) struct PendingApplication<'a> {
+    store: &'a IdempotencyStore,
+    key: &'a IdempotencyKey,
+    settled: bool,
+}
+
+impl PendingApplication<'_> {
+    pub(crate) fn complete(mut self, created: &LoanCreted) {
+        self.settled = true;
+        self.store
+            .set(self.key, ApplicationState::Completed(created.clone()));
+    }
+
+    /// forget an application the lender answered, keep one it may have received
+    pub(crate) fn fail(mut self, error: &SupremoError) {
+        self.settled = true;
+        match error {
+            SupremoError::Timeout(_) | SupremoError::Transport(_) => {
+                self.store.set(self.key, ApplicationState::Unknown)
+            }
+            _ => self.store.abandon(self.key),
+        }
+    }
+}
+
+impl Drop for PendingApplication<'_> {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.store.set(self.key, ApplicationState::Unknown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_keys() {
+        assert!(IdempotencyKey::new("").is_err());
+        assert!(IdempotencyKey::new("has space").is_err());
+        assert!(IdempotencyKey::new("x".repeat(256)).is_err());
+    }
+
+    fn started<'a>(store: &'a IdempotencyStore, key: &'a IdempotencyKey) -> PendingApplication<'a> {
+        match store.begin(key).unwrap() {
+            Begin::Started(pending) => pending,
+            Begin::Completed(_) => panic!("application already completed"),
+        }
+    }
+
+    #[test]
+    fn store_recognizes_repeated_applications() {
+        let store = IdempotencyStore::default();
+        let key = IdempotencyKey::new("order-1").unwrap();
+
+        let pending = started(&store, &key);
+        let err = store.clone().begin(&key).err().unwrap();
+        assert!(matches!(err, SupremoError::DuplicateRequest { .. }));
+
+        let created = LoanCreted {
+            message: "loan created".to_string(),
+        };
+        pending.complete(&created);
+        match store.begin(&key).unwrap() {
+            Begin::Completed(created) => assert_eq!(created.message, "loan created"),
+            Begin::Started(_) => panic!("completed application started again"),
+        }
+
+        let other = IdempotencyKey::new("order-2").unwrap();
+        let rejected = SupremoError::from_status(
+            reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+            serde_json::json!({"code": "insufficient_limit"}),
+        );
+        started(&store, &other).fail(&rejected);
+        assert!(store.state(&other).is_none());
+    }
+
+    #[test]
+    fn cancelled_applications_are_sent_again() {
+        let store = IdempotencyStore::default().with_in_flight_ttl(Duration::from_millis(20));
+        let key = IdempotencyKey::new("order-1").unwrap();
+
+        // dropped as when the caller's timeout cancels the call
+        drop(started(&store, &key));
+        assert!(matches!(store.state(&key), Some(ApplicationState::Unknown)));
+        let pending = started(&store, &key);
+
+        // an application left in flight is released after the in flight ttl
+        std::mem::forget(pending);
+        assert!(store.begin(&key).is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        started(&store, &key).complete(&LoanCreted {
+            message: "loan created".to_string(),
+        });
+        assert!(matches!(
+            store.state(&key),
+            Some(ApplicationState::Completed(_))
+        ));
+    }
+}
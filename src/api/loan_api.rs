@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::client::LoanClient;
+use super::error::Result;
+use super::types::{
+    AnchorPagination, CalculateLonaResponse, ClientLimit, LoanCreted, LoanInput, OuathToken,
+    PaginatedAnchors, UserSession,
+};
+
+/// Lender operations used by applications, implemented by [`LoanClient`].
+///
+/// Write service code against `LoanApi` (or `Arc<dyn LoanApi>`) to swap the client for
+/// a fake in tests or wrap it in decorators such as logging or caching.
+/// `bearer_token` may be `None` when the implementation manages its own token.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::error::Result;
+/// use supremo_loan::api::loan_api::LoanApi;
+///
+/// async fn can_borrow(lender: &dyn LoanApi, client_id: i32, amount: f64) -> Result<bool> {
+///     let limit = lender.client_limit(None, client_id).await?;
+///     Ok(limit.remaining_limit >= amount)
+/// }
+/// ```
+#[async_trait]
+pub trait LoanApi: Send + Sync {
+    async fn get_auth_token(&self) -> Result<OuathToken>;
+
+    async fn exchange_code_auth(&self, code: &str) -> Result<UserSession>;
+
+    async fn client_limit(&self, bearer_token: Option<&str>, client_id: i32)
+        -> Result<ClientLimit>;
+
+    async fn get_anchors(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+        pagination: Option<AnchorPagination>,
+    ) -> Result<PaginatedAnchors>;
+
+    async fn calculate_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<Vec<CalculateLonaResponse>>;
+
+    async fn apply_for_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<LoanCreted>;
+}
+
+#[async_trait]
+impl LoanApi for LoanClient {
+    async fn get_auth_token(&self) -> Result<OuathToken> {
+        LoanClient::get_auth_token(self).await
+    }
+
+    async fn exchange_code_auth(&self, code: &str) -> Result<UserSession> {
+        LoanClient::exchange_code_auth(self, code).await
+    }
+
+    async fn client_limit(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+    ) -> Result<ClientLimit> {
+        LoanClient::client_limit(self, bearer_token, client_id).await
+    }
+
+    async fn get_anchors(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+        pagination: Option<AnchorPagination>,
+    ) -> Result<PaginatedAnchors> {
+        LoanClient::get_anchors(self, bearer_token, client_id, pagination).await
+    }
+
+    async fn calculate_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<Vec<CalculateLonaResponse>> {
+        LoanClient::calculate_loan(self, bearer_token.map(str::to_string), body).await
+    }
+
+    async fn apply_for_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<LoanCreted> {
+        LoanClient::apply_for_loan(self, bearer_token.map(str::to_string), body).await
+    }
+}
+
+/// share one implementation between tasks, e.g. `Arc<dyn LoanApi>`
+#[async_trait]
+impl<T: LoanApi + ?Sized> LoanApi for Arc<T> {
+    async fn get_auth_token(&self) -> Result<OuathToken> {
+        (**self).get_auth_token().await
+    }
+
+    async fn exchange_code_auth(&self, code: &str) -> Result<UserSession> {
+        (**self).exchange_code_auth(code).await
+    }
+
+    async fn client_limit(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+    ) -> Result<ClientLimit> {
+        (**self).client_limit(bearer_token, client_id).await
+    }
+
+    async fn get_anchors(
+        &self,
+        bearer_token: Option<&str>,
+        client_id: i32,
+        pagination: Option<AnchorPagination>,
+    ) -> Result<PaginatedAnchors> {
+        (**self)
+            .get_anchors(bearer_token, client_id, pagination)
+            .await
+    }
+
+    async fn calculate_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<Vec<CalculateLonaResponse>> {
+        (**self).calculate_loan(bearer_token, body).await
+    }
+
+    async fn apply_for_loan(
+        &self,
+        bearer_token: Option<&str>,
+        body: Vec<LoanInput>,
+    ) -> Result<LoanCreted> {
+        (**self).apply_for_loan(bearer_token, body).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::error::SupremoError;
+
+    struct FixedLimit(f64);
+
+    #[async_trait]
+    impl LoanApi for FixedLimit {
+        async fn get_auth_token(&self) -> Result<OuathToken> {
+            Err(SupremoError::Config("not supported".to_string()))
+        }
+
+        async fn exchange_code_auth(&self, _code: &str) -> Result<UserSession> {
+            Err(SupremoError::Config("not supported".to_string()))
+        }
+
+        async fn client_limit(
+            &self,
+            _bearer_token: Option<&str>,
+            _client_id: i32,
+        ) -> Result<ClientLimit> {
+            Ok(ClientLimit {
+                remaining_limit: self.0,
+                total_limit: self.0,
+                used_limit: 0.0,
+            })
+        }
+
+        async fn get_anchors(
+            &self,
+            _bearer_token: Option<&str>,
+            _client_id: i32,
+            _pagination: Option<AnchorPagination>,
+        ) -> Result<PaginatedAnchors> {
+            Err(SupremoError::Config("not supported".to_string()))
+        }
+
+        async fn calculate_loan(
+            &self,
+            _bearer_token: Option<&str>,
+            _body: Vec<LoanInput>,
+        ) -> Result<Vec<CalculateLonaResponse>> {
+            Ok(vec![])
+        }
+
+        async fn apply_for_loan(
+            &self,
+            _bearer_token: Option<&str>,
+            _body: Vec<LoanInput>,
+        ) -> Result<LoanCreted> {
+            Err(SupremoError::Config("not supported".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_and_fakes_share_the_interface() {
+        let lenders: Vec<Arc<dyn LoanApi>> = vec![
+            Arc::new(FixedLimit(500.0)),
+            Arc::new(LoanClient::new(
+                String::from("http://localhost:8080"),
+                String::from("secret_key"),
+                String::from("public_key"),
+                String::from("bank_name"),
+                String::from("logo_url"),
+                String::from("http://127.0.0.1:8020/"),
+            )),
+        ];
+
+        let limit = lenders[0].client_limit(Some("token"), 1).await.unwrap();
+        assert_eq!(limit.remaining_limit, 500.0);
+        // validation happens before any request is sent
+        let err = lenders[1].calculate_loan(None, vec![]).await.unwrap_err();
+        assert!(matches!(err, SupremoError::Validation(_)));
+    }
+}
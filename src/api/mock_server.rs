@@ -0,0 +1,455 @@
+//! Local HTTP lender for integration tests, available with the `test-support` and `actix`
+//! features.
+//!
+//! [`MockServer`] serves the lender endpoints used by [`LoanClient`] on `127.0.0.1` and
+//! answers them from a [`FakeLender`], so tests go through the real HTTP path (form and
+//! basic auth on the token endpoint, bearer tokens, json bodies, error payloads and
+//! `Retry-After`) without a lender. The server runs on its own thread with its own actix
+//! system and stops when dropped.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Mutex};
+use std::thread::JoinHandle;
+
+use actix_web::http::StatusCode;
+use actix_web::rt::time::delay_for;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::client::LoanClient;
+use super::error::{Result, SupremoError};
+use super::fake::FakeLender;
+use super::idempotency::IDEMPOTENCY_KEY_HEADER;
+use super::loan_api::LoanApi;
+use super::types::{AnchorPagination, LoanCreted, LoanInput, UserToken};
+
+/// client id accepted by [`MockServer::start`]
+pub const MOCK_PUBLIC_KEY: &str = "mock_public_key";
+/// client secret accepted by [`MockServer::start`]
+pub const MOCK_SECRET_KEY: &str = "mock_secret_key";
+
+struct ServerState {
+    /// answers right away, handlers wait out its latency on the actix timer
+    lender: FakeLender,
+    /// accepted `public_key` and `secret_key`
+    credentials: Mutex<(String, String)>,
+    /// applications by idempotency key
+    applications: Mutex<HashMap<String, LoanCreted>>,
+    /// form fields received by the oauth endpoints, with the endpoint name
+    oauth_requests: Mutex<Vec<(String, HashMap<String, String>)>>,
+}
+
+/// Lender endpoints served over HTTP from a [`FakeLender`].
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::fake::FakeLender;
+/// use supremo_loan::api::mock_server::MockServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = MockServer::start(FakeLender::new().with_client(7, 10_000.0)).unwrap();
+/// let client = server.client();
+///
+/// let token = client.get_auth_token().await.unwrap();
+/// let limit = client.client_limit(token.access_token.expose(), 7).await.unwrap();
+/// assert_eq!(limit.remaining_limit, 10_000.0);
+/// # }
+/// ```
+pub struct MockServer {
+    addr: SocketAddr,
+    state: web::Data<ServerState>,
+    server: actix_web::dev::Server,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// serve `lender` on a free port, accepting [`MOCK_PUBLIC_KEY`] and [`MOCK_SECRET_KEY`]
+    pub fn start(lender: FakeLender) -> Result<Self> {
+        Self::start_with_credentials(lender, MOCK_PUBLIC_KEY, MOCK_SECRET_KEY)
+    }
+
+    pub fn start_with_credentials(
+        lender: FakeLender,
+        public_key: &str,
+        secret_key: &str,
+    ) -> Result<Self> {
+        let state = web::Data::new(ServerState {
+            lender: lender.without_latency(),
+            credentials: Mutex::new((public_key.to_string(), secret_key.to_string())),
+            applications: Mutex::new(HashMap::new()),
+            oauth_requests: Mutex::new(Vec::new()),
+        });
+
+        let (started, on_start) = mpsc::channel();
+        let server_state = state.clone();
+        let thread = std::thread::spawn(move || {
+            let system = actix_web::rt::System::new("supremo-mock-lender");
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(server_state.clone())
+                    .route("/api/v1/oauth/auth/token/", web::post().to(token))
+                    .route("/api/v1/oauth/auth/revoke/", web::post().to(revoke))
+                    .route("/api/v1/oauth/auth/introspect/", web::post().to(introspect))
+                    .route("/api/v1/oauth/auth/user", web::get().to(user))
+                    .route(
+                        "/api/v1/oauth/client-limit/{client_id}",
+                        web::get().to(client_limit),
+                    )
+                    .route(
+                        "/api/v1/oauth/client-anchors/{client_id}",
+                        web::get().to(anchors),
+                    )
+                    .route("/api/v1/oauth/calc-loan", web::post().to(calculate_loan))
+                    .route("/api/v1/oauth/apply-loan", web::post().to(apply_for_loan))
+            })
+            .workers(1)
+            .disable_signals()
+            .system_exit()
+            .bind("127.0.0.1:0");
+
+            match server {
+                Ok(server) => {
+                    let addr = server.addrs()[0];
+                    let server = server.run();
+                    let _ = started.send(Ok((addr, server)));
+                    let _ = system.run();
+                }
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                }
+            }
+        });
+
+        let (addr, server) = on_start
+            .recv()
+            .map_err(|_| SupremoError::Config("mock server thread exited".to_string()))?
+            .map_err(|e| SupremoError::Config(format!("could not start mock server: {}", e)))?;
+        Ok(Self {
+            addr,
+            state,
+            server,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// accept only `public_key` and `secret_key` from now on, as a lender rotating our keys
+    pub fn rotate_credentials(&self, public_key: &str, secret_key: &str) {
+        *self.state.credentials.lock().unwrap() = (public_key.to_string(), secret_key.to_string());
+    }
+
+    /// form fields received by `/api/v1/oauth/auth/{endpoint}/` (`token`, `revoke` or
+    /// `introspect`), in order
+    pub fn oauth_requests(&self, endpoint: &str) -> Vec<HashMap<String, String>> {
+        self.state
+            .oauth_requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name == endpoint)
+            .map(|(_, form)| form.clone())
+            .collect()
+    }
+
+    /// client for this server using the credentials it accepts
+    pub fn client(&self) -> LoanClient {
+        let (public_key, secret_key) = self.state.credentials.lock().unwrap().clone();
+        LoanClient::new(
+            self.base_url(),
+            secret_key,
+            public_key,
+            "mock lender".to_string(),
+            "logo_url".to_string(),
+            "http://127.0.0.1:8020/".to_string(),
+        )
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        // the command is sent right away, the returned future only reports completion
+        drop(self.server.stop(false));
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// wait out the latency of the lender on the actix runtime's own timer
+async fn wait(state: &ServerState) {
+    let latency = state.lender.latency();
+    if !latency.is_zero() {
+        delay_for(latency).await;
+    }
+}
+
+fn error_response(error: SupremoError) -> HttpResponse {
+    let status = error
+        .status()
+        .and_then(|status| StatusCode::from_u16(status.as_u16()).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = HttpResponse::build(status);
+    if let Some(retry_after) = error.retry_after() {
+        response.header("Retry-After", retry_after.as_secs().to_string());
+    }
+    response.json(error.body().cloned().unwrap_or_else(|| json!({})))
+}
+
+fn unauthorized(code: &str, message: &str) -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({"code": code, "message": message}))
+}
+
+fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// record the form sent to an oauth endpoint and check the client credentials from
+/// basic auth, or the `client_id` form field of public clients
+fn authenticate_client(
+    state: &ServerState,
+    endpoint: &str,
+    request: &HttpRequest,
+    form: &HashMap<String, String>,
+) -> bool {
+    state
+        .oauth_requests
+        .lock()
+        .unwrap()
+        .push((endpoint.to_string(), form.clone()));
+    let basic = request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let (public_key, secret_key) = &*state.credentials.lock().unwrap();
+    match basic {
+        Some(credentials) => credentials == format!("{}:{}", public_key, secret_key),
+        None => form.get("client_id") == Some(public_key),
+    }
+}
+
+async fn token(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    form: web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+    wait(&state).await;
+    if !authenticate_client(&state, "token", &request, &form) {
+        return unauthorized("invalid_client", "client authentication failed");
+    }
+
+    match form.get("grant_type").map(String::as_str) {
+        Some("client_credentials") => match state.lender.get_auth_token().await {
+            Ok(token) => HttpResponse::Ok().json(token),
+            Err(e) => error_response(e),
+        },
+        Some("authorization_code") => {
+            let code = form.get("code").map(String::as_str).unwrap_or_default();
+            match state.lender.exchange_code_auth(code).await {
+                Ok(session) => HttpResponse::Ok().json(token_response(&session.token, true)),
+                Err(e) => error_response(e),
+            }
+        }
+        Some("refresh_token") => {
+            let refresh_token = form.get("refresh_token").map(String::as_str);
+            match state
+                .lender
+                .refresh_token(refresh_token.unwrap_or_default())
+                .await
+            {
+                // a refresh token that was kept is left out, as lenders that do not rotate them
+                Ok(token) => {
+                    let rotated = Some(token.refresh_token.expose()) != refresh_token;
+                    HttpResponse::Ok().json(token_response(&token, rotated))
+                }
+                Err(e) => error_response(e),
+            }
+        }
+        _ => HttpResponse::BadRequest().json(json!({
+            "code": "unsupported_grant_type",
+            "message": "the grant type is not supported",
+        })),
+    }
+}
+
+fn token_response(token: &UserToken, with_refresh_token: bool) -> serde_json::Value {
+    let mut body = json!({
+        "access_token": token.access_token.expose(),
+        "token_type": token.token_type,
+        "expires_in": token.expires_in,
+        "scope": token.scope,
+    });
+    if with_refresh_token {
+        body["refresh_token"] = json!(token.refresh_token.expose());
+    }
+    body
+}
+
+/// answers `200` with an empty body, as RFC 7009 lenders do
+async fn revoke(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    form: web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+    wait(&state).await;
+    if !authenticate_client(&state, "revoke", &request, &form) {
+        return unauthorized("invalid_client", "client authentication failed");
+    }
+    let token = form.get("token").map(String::as_str).unwrap_or_default();
+    match state.lender.revoke_token(token).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn introspect(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    form: web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+    wait(&state).await;
+    if !authenticate_client(&state, "introspect", &request, &form) {
+        return unauthorized("invalid_client", "client authentication failed");
+    }
+    let token = form.get("token").map(String::as_str).unwrap_or_default();
+    match state.lender.introspect_token(token).await {
+        // inactive tokens come without any other field
+        Ok(introspection) if !introspection.active => {
+            HttpResponse::Ok().json(json!({"active": false}))
+        }
+        Ok(introspection) => HttpResponse::Ok().json(introspection),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn user(state: web::Data<ServerState>, request: HttpRequest) -> HttpResponse {
+    wait(&state).await;
+    let Some(token) = bearer_token(&request) else {
+        return unauthorized("invalid_token", "missing bearer token");
+    };
+    match state.lender.user_info(token).await {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn client_limit(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    client_id: web::Path<i32>,
+) -> HttpResponse {
+    wait(&state).await;
+    let Some(token) = bearer_token(&request) else {
+        return unauthorized("invalid_token", "missing bearer token");
+    };
+    match state.lender.client_limit(Some(token), *client_id).await {
+        Ok(limit) => HttpResponse::Ok().json(limit),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct AnchorQuery {
+    page: Option<i32>,
+    page_size: Option<i32>,
+    order: Option<String>,
+}
+
+async fn anchors(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    client_id: web::Path<i32>,
+    query: web::Query<AnchorQuery>,
+) -> HttpResponse {
+    wait(&state).await;
+    let Some(token) = bearer_token(&request) else {
+        return unauthorized("invalid_token", "missing bearer token");
+    };
+    let query = query.into_inner();
+    let pagination = AnchorPagination {
+        page: query.page,
+        page_size: query.page_size,
+        order: query.order,
+    };
+    match state
+        .lender
+        .get_anchors(Some(token), *client_id, Some(pagination))
+        .await
+    {
+        Ok(anchors) => HttpResponse::Ok().json(anchors),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn calculate_loan(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    body: web::Json<Vec<LoanInput>>,
+) -> HttpResponse {
+    wait(&state).await;
+    let Some(token) = bearer_token(&request) else {
+        return unauthorized("invalid_token", "missing bearer token");
+    };
+    match state
+        .lender
+        .calculate_loan(Some(token), body.into_inner())
+        .await
+    {
+        Ok(charges) => HttpResponse::Ok().json(charges),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn apply_for_loan(
+    state: web::Data<ServerState>,
+    request: HttpRequest,
+    body: web::Json<Vec<LoanInput>>,
+) -> HttpResponse {
+    wait(&state).await;
+    let Some(token) = bearer_token(&request) else {
+        return unauthorized("invalid_token", "missing bearer token");
+    };
+    let key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    if let Some(created) = key
+        .as_ref()
+        .and_then(|key| state.applications.lock().unwrap().get(key).cloned())
+    {
+        return HttpResponse::Ok().json(created);
+    }
+
+    match state
+        .lender
+        .apply_for_loan(Some(token), body.into_inner())
+        .await
+    {
+        Ok(created) => {
+            if let Some(key) = key {
+                state
+                    .applications
+                    .lock()
+                    .unwrap()
+                    .insert(key, created.clone());
+            }
+            HttpResponse::Ok().json(created)
+        }
+        Err(e) => error_response(e),
+    }
+}
@@ -1,3 +1,22 @@
-pub mod client;
 pub mod actions;
-pub mod types;
\ No newline at end of file
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cassette;
+pub mod client;
+pub mod config;
+pub mod credentials;
+pub mod error;
+#[cfg(any(test, feature = "test-support"))]
+pub mod fake;
+pub mod idempotency;
+pub mod loan_api;
+#[cfg(all(feature = "actix", any(test, feature = "test-support")))]
+pub mod mock_server;
+pub mod oauth;
+pub mod quotes;
+pub mod registry;
+pub mod retry;
+pub mod secrets;
+pub mod token;
+pub mod transport;
+pub mod types;
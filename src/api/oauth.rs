@@ -0,0 +1,132 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::error::{Result, SupremoError};
+use super::secrets::{serialize_exposed_option, SecretString};
+
+/// Authorization started with [`LoanClient::authorization_url`](super::client::LoanClient::authorization_url).
+///
+/// Send the borrower to `url` and keep the request (for instance in their server side
+/// session) until the lender redirects back, then hand it to
+/// [`LoanClient::exchange_authorization_callback`](super::client::LoanClient::exchange_authorization_callback)
+/// which rejects callbacks whose `state` does not match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    /// PKCE verifier sent with the code exchange, set when the client uses PKCE
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_exposed_option"
+    )]
+    pub code_verifier: Option<SecretString>,
+}
+
+impl AuthorizationRequest {
+    /// check the `state` returned on the redirect against the one sent
+    ///
+    /// # Examples
+    /// ```
+    /// use supremo_loan::api::oauth::AuthorizationRequest;
+    ///
+    /// let request = AuthorizationRequest {
+    ///     url: "https://lender.example.com/api/v1/oauth/auth/authorize".to_string(),
+    ///     state: "expected".to_string(),
+    ///     code_verifier: None,
+    /// };
+    /// assert!(request.verify_state(Some("expected")).is_ok());
+    /// assert!(request.verify_state(Some("forged")).is_err());
+    /// assert!(request.verify_state(None).is_err());
+    /// ```
+    pub fn verify_state(&self, returned_state: Option<&str>) -> Result<()> {
+        match returned_state {
+            None => Err(SupremoError::InvalidState(
+                "callback is missing the state parameter".to_string(),
+            )),
+            Some(state) if constant_time_eq(state.as_bytes(), self.state.as_bytes()) => Ok(()),
+            Some(_) => Err(SupremoError::InvalidState(
+                "callback state does not match the authorization request".to_string(),
+            )),
+        }
+    }
+}
+
+/// PKCE (RFC 7636) verifier and its `S256` challenge.
+///
+/// The challenge goes in the authorize url and the verifier is sent with the code
+/// exchange, proving both requests come from the same party without a client secret.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::oauth::PkceChallenge;
+///
+/// // example from RFC 7636 appendix B
+/// let pkce = PkceChallenge::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+/// assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+/// assert_eq!(PkceChallenge::METHOD, "S256");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkceChallenge {
+    pub verifier: SecretString,
+    pub challenge: String,
+}
+
+impl PkceChallenge {
+    pub const METHOD: &'static str = "S256";
+
+    pub fn generate() -> Self {
+        Self::from_verifier(random_token())
+    }
+
+    pub fn from_verifier(verifier: impl Into<SecretString>) -> Self {
+        let verifier = verifier.into();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.expose().as_bytes()));
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// random url safe value with 256 bits of entropy
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// compare without returning early so the time taken does not leak the expected value
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_tokens_are_unique_and_url_safe() {
+        let a = random_token();
+        let b = random_token();
+        assert_ne!(a, b);
+        assert_eq!(a.len(), 43);
+        assert!(a
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn verifiers_are_not_printed() {
+        let pkce = PkceChallenge::generate();
+        let request = AuthorizationRequest {
+            url: "https://lender.example.com/api/v1/oauth/auth/authorize".to_string(),
+            state: "state".to_string(),
+            code_verifier: Some(pkce.verifier.clone()),
+        };
+        assert!(!format!("{:?}", pkce).contains(pkce.verifier.expose()));
+        assert!(!format!("{:?}", request).contains(pkce.verifier.expose()));
+    }
+}
@@ -0,0 +1,378 @@
+//! Loan quotes compared across lenders.
+//!
+//! A [`QuoteAggregator`] sends the same [`LoanInput`] to every lender's `calculate_loan`
+//! at once, waits at most its deadline for each of them and returns the quotes ranked by
+//! total cost with the lenders that failed or were too slow, so borrowers can compare
+//! offers even when some lenders are down.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+
+use super::error::SupremoError;
+use super::loan_api::LoanApi;
+use super::registry::{LenderMetadata, LenderRegistry};
+use super::secrets::SecretString;
+use super::types::{CalculateLonaResponse, LinkedAccount, LoanInput};
+
+/// Offer of one lender.
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub lender: String,
+    pub charges: CalculateLonaResponse,
+    /// time the lender took to answer
+    pub elapsed: Duration,
+}
+
+impl Quote {
+    /// what the borrower pays back, used for ranking
+    pub fn total_cost(&self) -> f64 {
+        self.charges.total
+    }
+}
+
+/// Why a lender has no quote.
+#[derive(Debug)]
+pub enum QuoteError {
+    /// the lender did not answer within the deadline
+    DeadlineExceeded(Duration),
+    /// the lender answered without a quote
+    NoQuote,
+    /// the request failed
+    Lender(SupremoError),
+}
+
+impl fmt::Display for QuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuoteError::DeadlineExceeded(deadline) => {
+                write!(f, "no answer within {}ms", deadline.as_millis())
+            }
+            QuoteError::NoQuote => f.write_str("the lender returned no quote"),
+            QuoteError::Lender(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for QuoteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QuoteError::Lender(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A lender without a quote and the reason.
+#[derive(Debug)]
+pub struct QuoteFailure {
+    pub lender: String,
+    pub error: QuoteError,
+}
+
+/// Result of [`QuoteAggregator::quote`].
+#[derive(Debug, Default)]
+pub struct Quotes {
+    /// cheapest first
+    pub quotes: Vec<Quote>,
+    /// lenders that failed or missed the deadline, in the order they were added
+    pub failures: Vec<QuoteFailure>,
+}
+
+impl Quotes {
+    pub fn best(&self) -> Option<&Quote> {
+        self.quotes.first()
+    }
+
+    /// whether every lender answered with a quote
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+struct Target {
+    lender: String,
+    api: Arc<dyn LoanApi>,
+    bearer_token: Option<SecretString>,
+    metadata: Option<LenderMetadata>,
+    /// the borrower's `client_id` and `anchor_id` at this lender
+    borrower: Option<(i32, i32)>,
+}
+
+/// Fans a loan out to several lenders and ranks their quotes.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use supremo_loan::api::quotes::QuoteAggregator;
+/// use supremo_loan::api::registry::LenderRegistry;
+/// use supremo_loan::api::types::LoanInput;
+///
+/// # async fn run(lenders: LenderRegistry, loan: LoanInput) {
+/// let quotes = QuoteAggregator::from_registry(&lenders)
+///     .deadline(Duration::from_secs(2))
+///     .quote(&loan)
+///     .await;
+/// for quote in &quotes.quotes {
+///     println!("{}: {}", quote.lender, quote.total_cost());
+/// }
+/// for failure in &quotes.failures {
+///     println!("{} has no offer: {}", failure.lender, failure.error);
+/// }
+/// # }
+/// ```
+pub struct QuoteAggregator {
+    targets: Vec<Target>,
+    deadline: Duration,
+}
+
+impl Default for QuoteAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QuoteAggregator {
+    pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(5);
+
+    pub fn new() -> Self {
+        Self {
+            targets: Vec::new(),
+            deadline: Self::DEFAULT_DEADLINE,
+        }
+    }
+
+    /// how long each lender is waited for, lenders are asked at the same time
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// ask `api` as `lender`, `bearer_token` is `None` when the client manages its own token
+    pub fn lender(
+        mut self,
+        lender: impl Into<String>,
+        api: impl LoanApi + 'static,
+        bearer_token: Option<String>,
+    ) -> Self {
+        self.targets.push(Target {
+            lender: lender.into(),
+            api: Arc::new(api),
+            bearer_token: bearer_token.map(SecretString::from),
+            metadata: None,
+            borrower: None,
+        });
+        self
+    }
+
+    /// every enabled lender of `registry`, asked without a bearer token so their clients
+    /// must manage their own, as those built from a [`LenderConfig`](super::config::LenderConfig)
+    /// do unless `tokens.manage` is off
+    ///
+    /// lenders whose metadata does not list the loan type are not asked
+    pub fn from_registry(registry: &LenderRegistry) -> Self {
+        let mut aggregator = Self::new();
+        for entry in registry.entries().into_iter().filter(|e| e.is_enabled()) {
+            aggregator.targets.push(Target {
+                lender: entry.name().to_string(),
+                api: Arc::new(entry.client().clone()),
+                bearer_token: None,
+                metadata: Some(entry.metadata().clone()),
+                borrower: None,
+            });
+        }
+        aggregator
+    }
+
+    /// the enabled lenders a borrower linked, asked with the borrower's token and with
+    /// `client_id` and `anchor_id` replaced by the borrower's ids at each lender
+    pub fn for_accounts(registry: &LenderRegistry, accounts: &[LinkedAccount]) -> Self {
+        let mut aggregator = Self::new();
+        for account in accounts {
+            let Some(entry) = registry
+                .entry(&account.lender_name)
+                .filter(|e| e.is_enabled())
+            else {
+                continue;
+            };
+            aggregator.targets.push(Target {
+                lender: entry.name().to_string(),
+                api: Arc::new(entry.client().clone()),
+                bearer_token: Some(account.token.access_token.clone()),
+                metadata: Some(entry.metadata().clone()),
+                borrower: Some((account.user.id, account.user.anchor_id)),
+            });
+        }
+        aggregator
+    }
+
+    /// names of the lenders that would be asked for `loan_type`
+    pub fn lenders(&self, loan_type: &str) -> Vec<&str> {
+        self.targets
+            .iter()
+            .filter(|target| target.supports(loan_type))
+            .map(|target| target.lender.as_str())
+            .collect()
+    }
+
+    /// ask every lender supporting the loan type for a quote, never waiting longer
+    /// than the deadline
+    pub async fn quote(&self, input: &LoanInput) -> Quotes {
+        let requests = self
+            .targets
+            .iter()
+            .filter(|target| target.supports(&input.loan_type))
+            .map(|target| self.ask(target, input));
+        let mut quotes = Quotes::default();
+        for (lender, result) in join_all(requests).await {
+            match result {
+                Ok(quote) => quotes.quotes.push(quote),
+                Err(error) => quotes.failures.push(QuoteFailure { lender, error }),
+            }
+        }
+        quotes
+            .quotes
+            .sort_by(|a, b| a.total_cost().total_cmp(&b.total_cost()));
+        quotes
+    }
+
+    async fn ask(&self, target: &Target, input: &LoanInput) -> (String, Result<Quote, QuoteError>) {
+        let mut input = input.clone();
+        if let Some((client_id, anchor_id)) = target.borrower {
+            input.client_id = client_id;
+            input.anchor_id = anchor_id;
+        }
+        let started = Instant::now();
+        let bearer_token = target.bearer_token.as_ref().map(SecretString::expose);
+        let request = target.api.calculate_loan(bearer_token, vec![input]);
+        let result = match tokio::time::timeout(self.deadline, request).await {
+            Err(_) => Err(QuoteError::DeadlineExceeded(self.deadline)),
+            Ok(Err(e)) => Err(QuoteError::Lender(e)),
+            Ok(Ok(charges)) => match charges.into_iter().next() {
+                Some(charges) => Ok(Quote {
+                    lender: target.lender.clone(),
+                    charges,
+                    elapsed: started.elapsed(),
+                }),
+                None => Err(QuoteError::NoQuote),
+            },
+        };
+        (target.lender.clone(), result)
+    }
+}
+
+impl Target {
+    fn supports(&self, loan_type: &str) -> bool {
+        match &self.metadata {
+            Some(metadata) => metadata.supports(loan_type),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::{anchor, Failure, FakeLender, FeeSchedule};
+    use crate::api::retry::Endpoint;
+
+    fn lender(monthly_interest_rate: f64) -> FakeLender {
+        FakeLender::new()
+            .with_client(1, 10_000.0)
+            .with_anchor(1, anchor(3, 5_000.0))
+            .with_fees(FeeSchedule {
+                monthly_interest_rate,
+                ..FeeSchedule::default()
+            })
+    }
+
+    fn loan() -> LoanInput {
+        LoanInput {
+            amount: 1_000.0,
+            anchor_id: 3,
+            client_id: 1,
+            loan_term: 30,
+            loan_type: "invoice".to_string(),
+            metadata: serde_json::json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_and_failing_lenders_leave_partial_results() {
+        let down = lender(0.01);
+        down.fail_next(Endpoint::CalculateLoan, Failure::ServerError);
+        let aggregator = QuoteAggregator::new()
+            .deadline(Duration::from_millis(100))
+            .lender("dear bank", lender(0.05), None)
+            .lender(
+                "slow bank",
+                lender(0.0).with_latency(Duration::from_secs(5)),
+                None,
+            )
+            .lender("down bank", down, None)
+            .lender("cheap bank", lender(0.02), None);
+
+        let started = Instant::now();
+        let quotes = aggregator.quote(&loan()).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let ranked: Vec<_> = quotes.quotes.iter().map(|q| q.lender.as_str()).collect();
+        assert_eq!(ranked, ["cheap bank", "dear bank"]);
+        assert!(quotes.quotes[0].total_cost() < quotes.quotes[1].total_cost());
+
+        let failed: Vec<_> = quotes
+            .failures
+            .iter()
+            .map(|f| (f.lender.as_str(), f.error.to_string()))
+            .collect();
+        assert_eq!(
+            failed[0],
+            ("slow bank", "no answer within 100ms".to_string())
+        );
+        assert_eq!(failed[1].0, "down bank");
+        assert!(matches!(
+            quotes.failures[1].error,
+            QuoteError::Lender(ref e) if e.status().is_some()
+        ));
+    }
+
+    #[cfg(feature = "actix")]
+    #[tokio::test]
+    async fn lenders_configured_from_a_file_are_asked() {
+        use crate::api::config::{ConfigFormat, LendersConfig};
+        use crate::api::mock_server::{MockServer, MOCK_PUBLIC_KEY, MOCK_SECRET_KEY};
+
+        let dear = MockServer::start(lender(0.05)).unwrap();
+        let cheap = MockServer::start(lender(0.02)).unwrap();
+        let entry = |name: &str, server: &MockServer| {
+            format!(
+                r#"{{"name": "{}", "base_url": "{}", "redirect_url": "https://app.example.com/callback",
+                    "public_key": {{"value": "{}"}}, "secret_key": {{"value": "{}"}},
+                    "loan_types": ["invoice"]}}"#,
+                name,
+                server.base_url(),
+                MOCK_PUBLIC_KEY,
+                MOCK_SECRET_KEY
+            )
+        };
+        let config = LendersConfig::parse(
+            &format!(
+                r#"{{"lenders": [{}, {}]}}"#,
+                entry("dear bank", &dear),
+                entry("cheap bank", &cheap)
+            ),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        let registry = LenderRegistry::from_config(&config).unwrap();
+
+        let quotes = QuoteAggregator::from_registry(&registry)
+            .quote(&loan())
+            .await;
+        assert!(quotes.is_complete(), "{:?}", quotes.failures);
+        let ranked: Vec<_> = quotes.quotes.iter().map(|q| q.lender.as_str()).collect();
+        assert_eq!(ranked, ["cheap bank", "dear bank"]);
+    }
+}
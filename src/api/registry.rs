@@ -0,0 +1,647 @@
+//! Lender clients looked up by name.
+//!
+//! A [`LenderRegistry`] holds one [`LoanClient`] per lender with its [`LenderMetadata`]
+//! and an enabled flag, so routing code asks for "the client of Access Bank" instead of
+//! indexing into a list. Registries loaded from a configuration file can be reloaded
+//! while running: lenders whose configuration did not change keep their client (and so
+//! their cached token), the others are rebuilt, added or removed.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use super::client::LoanClient;
+use super::config::{LenderConfig, LendersConfig};
+use super::error::{Result, SupremoError};
+use super::secrets::{EnvProvider, SecretProvider};
+
+/// Descriptive information about a lender, for listing and routing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct LenderMetadata {
+    pub display_name: String,
+    pub logo_url: Option<String>,
+    /// loan types the lender accepts, empty when not restricted
+    pub loan_types: Vec<String>,
+}
+
+impl LenderMetadata {
+    /// metadata of a client built without a configuration, accepting any loan type
+    pub fn from_client(client: &LoanClient) -> Self {
+        Self {
+            display_name: client.name.clone(),
+            logo_url: client.logo_url.clone(),
+            loan_types: Vec::new(),
+        }
+    }
+
+    pub fn supports(&self, loan_type: &str) -> bool {
+        self.loan_types.is_empty() || self.loan_types.iter().any(|t| t == loan_type)
+    }
+}
+
+/// A lender of a [`LenderRegistry`].
+#[derive(Debug, Clone)]
+pub struct LenderEntry {
+    client: LoanClient,
+    metadata: LenderMetadata,
+    enabled: bool,
+    /// set by [`LenderRegistry::enable`] and [`LenderRegistry::disable`], kept across reloads
+    overridden: Option<bool>,
+    config: Option<LenderConfig>,
+}
+
+impl LenderEntry {
+    pub fn name(&self) -> &str {
+        &self.client.name
+    }
+
+    pub fn client(&self) -> &LoanClient {
+        &self.client
+    }
+
+    pub fn metadata(&self) -> &LenderMetadata {
+        &self.metadata
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// Lenders added, rebuilt and removed by a reload, by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ReloadSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    secrets: Arc<dyn SecretProvider>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// by lower case name, lender names are unique regardless of case
+    lenders: BTreeMap<String, LenderEntry>,
+    source: Option<Source>,
+}
+
+/// Lender clients by name, shared by clones.
+///
+/// Names are matched regardless of case. Disabled lenders stay registered but are not
+/// returned by [`LenderRegistry::get`], [`LenderRegistry::clients`] and
+/// [`LenderRegistry::supporting`].
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::client::LoanClient;
+/// use supremo_loan::api::registry::LenderRegistry;
+///
+/// let client = |name: &str| {
+///     LoanClient::builder()
+///         .base_url("https://lender.example.com")
+///         .public_key("public_key")
+///         .secret_key("secret_key")
+///         .name(name)
+///         .redirect_url("https://app.example.com/callback")
+///         .build()
+///         .unwrap()
+/// };
+/// let registry = LenderRegistry::from_clients([client("Access Bank"), client("Other Bank")]).unwrap();
+///
+/// assert_eq!(registry.get("access bank").unwrap().name, "Access Bank");
+/// registry.disable("Access Bank");
+/// assert!(registry.get("Access Bank").is_none());
+/// assert_eq!(registry.names(), ["Access Bank", "Other Bank"]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LenderRegistry {
+    inner: Arc<RwLock<Inner>>,
+    /// held for a whole reload, so reloads do not interleave
+    reloading: Arc<Mutex<()>>,
+}
+
+fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+impl LenderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register clients built without a configuration, names must be unique
+    pub fn from_clients(clients: impl IntoIterator<Item = LoanClient>) -> Result<Self> {
+        let registry = Self::new();
+        for client in clients {
+            if registry.contains(&client.name) {
+                return Err(SupremoError::Config(format!(
+                    "lender {:?} is registered more than once",
+                    client.name
+                )));
+            }
+            let metadata = LenderMetadata::from_client(&client);
+            registry.insert(client, metadata);
+        }
+        Ok(registry)
+    }
+
+    /// build the clients of `config`, reading keys left out of it from the environment
+    pub fn from_config(config: &LendersConfig) -> Result<Self> {
+        let registry = Self::new();
+        registry.apply(config, &EnvProvider::default())?;
+        Ok(registry)
+    }
+
+    /// load `path`, which [`LenderRegistry::reload`] reads again, keys left out of the
+    /// configuration are read from the environment
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_path_with(path, EnvProvider::default())
+    }
+
+    /// like [`LenderRegistry::from_path`], looking keys up in `secrets`
+    pub fn from_path_with(
+        path: impl AsRef<Path>,
+        secrets: impl SecretProvider + 'static,
+    ) -> Result<Self> {
+        let registry = Self::new();
+        registry.inner.write().unwrap().source = Some(Source {
+            path: path.as_ref().to_path_buf(),
+            modified: None,
+            secrets: Arc::new(secrets),
+        });
+        registry.reload()?;
+        Ok(registry)
+    }
+
+    /// add or replace a lender, enabled
+    ///
+    /// reloads keep it, even over a configured lender of the same name, until it is removed
+    pub fn insert(&self, client: LoanClient, metadata: LenderMetadata) -> Option<LenderEntry> {
+        let entry = LenderEntry {
+            client,
+            metadata,
+            enabled: true,
+            overridden: None,
+            config: None,
+        };
+        self.inner
+            .write()
+            .unwrap()
+            .lenders
+            .insert(key(entry.name()), entry)
+    }
+
+    pub fn remove(&self, name: &str) -> Option<LenderEntry> {
+        self.inner.write().unwrap().lenders.remove(&key(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.read().unwrap().lenders.contains_key(&key(name))
+    }
+
+    /// the client of an enabled lender
+    pub fn get(&self, name: &str) -> Option<LoanClient> {
+        self.entry(name)
+            .filter(LenderEntry::is_enabled)
+            .map(|entry| entry.client)
+    }
+
+    /// the lender, enabled or not
+    pub fn entry(&self, name: &str) -> Option<LenderEntry> {
+        self.inner.read().unwrap().lenders.get(&key(name)).cloned()
+    }
+
+    /// every lender, enabled or not, ordered by name
+    pub fn entries(&self) -> Vec<LenderEntry> {
+        self.inner
+            .read()
+            .unwrap()
+            .lenders
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// names of every lender, enabled or not
+    pub fn names(&self) -> Vec<String> {
+        self.inner
+            .read()
+            .unwrap()
+            .lenders
+            .values()
+            .map(|entry| entry.name().to_string())
+            .collect()
+    }
+
+    /// clients of the enabled lenders
+    pub fn clients(&self) -> Vec<LoanClient> {
+        self.enabled(|_| true)
+    }
+
+    /// clients of the enabled lenders accepting `loan_type`
+    pub fn supporting(&self, loan_type: &str) -> Vec<LoanClient> {
+        self.enabled(|entry| entry.metadata.supports(loan_type))
+    }
+
+    fn enabled(&self, filter: impl Fn(&LenderEntry) -> bool) -> Vec<LoanClient> {
+        self.inner
+            .read()
+            .unwrap()
+            .lenders
+            .values()
+            .filter(|entry| entry.enabled && filter(entry))
+            .map(|entry| entry.client.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().lenders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// hand the lender out again, returns whether it is registered
+    ///
+    /// overrides the configuration's `enabled` until the lender is removed
+    pub fn enable(&self, name: &str) -> bool {
+        self.set_enabled(name, true)
+    }
+
+    /// stop handing the lender out, returns whether it is registered
+    ///
+    /// overrides the configuration's `enabled` until the lender is removed
+    pub fn disable(&self, name: &str) -> bool {
+        self.set_enabled(name, false)
+    }
+
+    fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        match inner.lenders.get_mut(&key(name)) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                entry.overridden = Some(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// read the configuration file again and apply it
+    ///
+    /// nothing changes when the file or one of its lenders is invalid
+    pub fn reload(&self) -> Result<ReloadSummary> {
+        let _reloading = self.reloading.lock().unwrap();
+        self.reload_locked()
+    }
+
+    fn reload_locked(&self) -> Result<ReloadSummary> {
+        let (path, secrets) = self.source()?;
+        let modified = modified(&path);
+        let config = LendersConfig::from_path(&path)?;
+        let summary = self.apply_locked(&config, secrets.as_ref())?;
+        if let Some(source) = &mut self.inner.write().unwrap().source {
+            source.modified = modified;
+        }
+        Ok(summary)
+    }
+
+    /// [`LenderRegistry::reload`] when the file changed since it was last read, for polling
+    pub fn reload_if_modified(&self) -> Result<Option<ReloadSummary>> {
+        let _reloading = self.reloading.lock().unwrap();
+        let (path, _) = self.source()?;
+        let last = self
+            .inner
+            .read()
+            .unwrap()
+            .source
+            .as_ref()
+            .and_then(|source| source.modified);
+        match (modified(&path), last) {
+            (Some(modified), Some(last)) if modified <= last => Ok(None),
+            _ => self.reload_locked().map(Some),
+        }
+    }
+
+    fn source(&self) -> Result<(PathBuf, Arc<dyn SecretProvider>)> {
+        let inner = self.inner.read().unwrap();
+        let source = inner.source.as_ref().ok_or_else(|| {
+            SupremoError::Config("the registry was not loaded from a file".to_string())
+        })?;
+        Ok((source.path.clone(), source.secrets.clone()))
+    }
+
+    /// replace the registered lenders with those of `config`
+    ///
+    /// lenders whose configuration did not change keep their client, lenders enabled or
+    /// disabled at runtime keep that state and lenders inserted at runtime are kept;
+    /// nothing changes when a client cannot be built
+    pub fn apply(
+        &self,
+        config: &LendersConfig,
+        secrets: &dyn SecretProvider,
+    ) -> Result<ReloadSummary> {
+        let _reloading = self.reloading.lock().unwrap();
+        self.apply_locked(config, secrets)
+    }
+
+    fn apply_locked(
+        &self,
+        config: &LendersConfig,
+        secrets: &dyn SecretProvider,
+    ) -> Result<ReloadSummary> {
+        // build outside of the lock, reading keys may touch the file system
+        let snapshot: BTreeMap<String, LenderEntry> = self.inner.read().unwrap().lenders.clone();
+        let mut built = Vec::new();
+        for lender in &config.lenders {
+            let previous = snapshot.get(&key(&lender.name));
+            let (client, change) = match previous {
+                Some(entry) if entry.config.is_none() => continue,
+                Some(entry) if entry.config.as_ref() == Some(lender) => {
+                    (entry.client.clone(), None)
+                }
+                Some(_) => (lender.client_with(secrets)?, Some(Change::Updated)),
+                None => (lender.client_with(secrets)?, Some(Change::Added)),
+            };
+            let entry = LenderEntry {
+                client,
+                metadata: lender.metadata(),
+                enabled: lender.enabled,
+                overridden: None,
+                config: Some(lender.clone()),
+            };
+            built.push((key(&lender.name), entry, change));
+        }
+
+        // enable, disable, insert and remove may have run while building, apply the
+        // configuration to the lenders as they are now
+        let mut inner = self.inner.write().unwrap();
+        let lenders = &mut inner.lenders;
+        let mut summary = ReloadSummary::default();
+        let configured: Vec<String> = built.iter().map(|(key, _, _)| key.clone()).collect();
+        summary.removed = lenders
+            .iter()
+            .filter(|(key, entry)| entry.config.is_some() && !configured.contains(key))
+            .map(|(_, entry)| entry.name().to_string())
+            .collect();
+        lenders.retain(|key, entry| entry.config.is_none() || configured.contains(key));
+        for (key, mut entry, change) in built {
+            match lenders.get(&key) {
+                // inserted at runtime
+                Some(current) if current.config.is_none() => continue,
+                Some(current) => {
+                    entry.overridden = current.overridden;
+                    entry.enabled = current.overridden.unwrap_or(entry.enabled);
+                }
+                // removed at runtime
+                None if snapshot.contains_key(&key) => continue,
+                None => {}
+            }
+            match change {
+                Some(Change::Added) => summary.added.push(entry.name().to_string()),
+                Some(Change::Updated) => summary.updated.push(entry.name().to_string()),
+                None => {}
+            }
+            lenders.insert(key, entry);
+        }
+        Ok(summary)
+    }
+}
+
+enum Change {
+    Added,
+    Updated,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::config::ConfigFormat;
+    use crate::api::secrets::{DotenvProvider, KeyKind, SecretString};
+
+    fn config(lenders: &str) -> LendersConfig {
+        LendersConfig::parse(lenders, ConfigFormat::Json).unwrap()
+    }
+
+    #[test]
+    fn reload_keeps_unchanged_lenders_and_runtime_flags() {
+        let secrets = DotenvProvider::parse(
+            "ACCESS_BANK_PUBLIC_KEY=public\nACCESS_BANK_SECRET_KEY=secret\n\
+             OTHER_BANK_PUBLIC_KEY=public\nOTHER_BANK_SECRET_KEY=secret\n\
+             NEW_BANK_PUBLIC_KEY=public\nNEW_BANK_SECRET_KEY=secret",
+        );
+        let registry = LenderRegistry::new();
+        let summary = registry
+            .apply(
+                &config(
+                    r#"{"lenders": [
+                        {"name": "Access Bank", "base_url": "https://access.example.com",
+                         "redirect_url": "https://app.example.com/callback",
+                         "display_name": "Access", "loan_types": ["invoice"]},
+                        {"name": "Other Bank", "base_url": "https://other.example.com",
+                         "redirect_url": "https://app.example.com/callback"}
+                    ]}"#,
+                ),
+                &secrets,
+            )
+            .unwrap();
+        assert_eq!(summary.added, ["Access Bank", "Other Bank"]);
+        assert_eq!(
+            registry
+                .entry("access bank")
+                .unwrap()
+                .metadata()
+                .display_name,
+            "Access"
+        );
+        assert_eq!(registry.supporting("invoice").len(), 2);
+        assert_eq!(registry.supporting("asset").len(), 1);
+        registry.disable("Access Bank");
+
+        let summary = registry
+            .apply(
+                &config(
+                    r#"{"lenders": [
+                        {"name": "Access Bank", "base_url": "https://access.example.com",
+                         "redirect_url": "https://app.example.com/callback",
+                         "display_name": "Access", "loan_types": ["invoice"]},
+                        {"name": "New Bank", "base_url": "https://new.example.com",
+                         "redirect_url": "https://app.example.com/callback", "enabled": false}
+                    ]}"#,
+                ),
+                &secrets,
+            )
+            .unwrap();
+        assert_eq!(
+            summary,
+            ReloadSummary {
+                added: vec!["New Bank".to_string()],
+                updated: vec![],
+                removed: vec!["Other Bank".to_string()],
+            }
+        );
+        assert!(registry.get("Access Bank").is_none());
+        assert!(!registry.entry("New Bank").unwrap().is_enabled());
+        assert!(registry.clients().is_empty());
+
+        // a lender that cannot be built leaves the registry as it was
+        let err = registry
+            .apply(
+                &config(
+                    r#"{"lenders": [{"name": "Unknown Bank", "base_url": "https://unknown.example.com",
+                        "redirect_url": "https://app.example.com/callback"}]}"#,
+                ),
+                &secrets,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("UNKNOWN_BANK_PUBLIC_KEY"));
+        assert_eq!(registry.names(), ["Access Bank", "New Bank"]);
+    }
+
+    /// provider changing the registry the first time keys are read, as calls landing
+    /// while a reload builds its clients
+    #[derive(Debug)]
+    struct Meddling {
+        secrets: DotenvProvider,
+        registry: LenderRegistry,
+        meddled: std::sync::atomic::AtomicBool,
+    }
+
+    impl SecretProvider for Meddling {
+        fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+            if !self.meddled.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                self.registry.disable("Access Bank");
+                self.registry.remove("Other Bank");
+                let mut manual = self.registry.entry("Access Bank").unwrap().client;
+                manual.name = "Manual Bank".to_string();
+                let metadata = LenderMetadata::from_client(&manual);
+                self.registry.insert(manual, metadata);
+            }
+            self.secrets.secret(lender, kind)
+        }
+
+        fn location(&self, lender: &str, kind: KeyKind) -> String {
+            self.secrets.location(lender, kind)
+        }
+    }
+
+    #[test]
+    fn runtime_changes_during_a_reload_are_kept() {
+        let secrets = DotenvProvider::parse(
+            "ACCESS_BANK_PUBLIC_KEY=public\nACCESS_BANK_SECRET_KEY=secret\n\
+             OTHER_BANK_PUBLIC_KEY=public\nOTHER_BANK_SECRET_KEY=secret\n\
+             NEW_BANK_PUBLIC_KEY=public\nNEW_BANK_SECRET_KEY=secret",
+        );
+        let lenders = |base_url: &str, names: &[&str]| {
+            let lenders: Vec<String> = names
+                .iter()
+                .map(|name| {
+                    format!(
+                        r#"{{"name": "{}", "base_url": "{}",
+                            "redirect_url": "https://app.example.com/callback"}}"#,
+                        name, base_url
+                    )
+                })
+                .collect();
+            config(&format!(r#"{{"lenders": [{}]}}"#, lenders.join(",")))
+        };
+        let registry = LenderRegistry::new();
+        registry
+            .apply(
+                &lenders("https://v1.example.com", &["Access Bank", "Other Bank"]),
+                &secrets,
+            )
+            .unwrap();
+
+        let meddling = Meddling {
+            secrets,
+            registry: registry.clone(),
+            meddled: Default::default(),
+        };
+        let summary = registry
+            .apply(
+                &lenders(
+                    "https://v2.example.com",
+                    &["Access Bank", "Other Bank", "New Bank"],
+                ),
+                &meddling,
+            )
+            .unwrap();
+        assert_eq!(
+            summary,
+            ReloadSummary {
+                added: vec!["New Bank".to_string()],
+                updated: vec!["Access Bank".to_string()],
+                removed: vec![],
+            }
+        );
+        assert_eq!(registry.names(), ["Access Bank", "Manual Bank", "New Bank"]);
+        assert!(!registry.entry("Access Bank").unwrap().is_enabled());
+        assert_eq!(
+            registry.entry("Access Bank").unwrap().client().base_url,
+            "https://v2.example.com"
+        );
+
+        // the inserted lender is not configured and stays
+        registry
+            .apply(
+                &lenders("https://v2.example.com", &["Access Bank"]),
+                &meddling,
+            )
+            .unwrap();
+        assert_eq!(registry.names(), ["Access Bank", "Manual Bank"]);
+    }
+
+    #[test]
+    fn reload_reads_the_file_again() {
+        let path =
+            std::env::temp_dir().join(format!("supremo-registry-{}.json", std::process::id()));
+        let lender = |name: &str| {
+            format!(
+                r#"{{"name": "{}", "base_url": "https://lender.example.com",
+                    "redirect_url": "https://app.example.com/callback",
+                    "public_key": {{"value": "public"}}, "secret_key": {{"value": "secret"}}}}"#,
+                name
+            )
+        };
+        fs::write(
+            &path,
+            format!(r#"{{"lenders": [{}]}}"#, lender("Access Bank")),
+        )
+        .unwrap();
+        let registry = LenderRegistry::from_path(&path).unwrap();
+        assert_eq!(registry.reload_if_modified().unwrap(), None);
+
+        let both = format!(
+            r#"{{"lenders": [{}, {}]}}"#,
+            lender("Access Bank"),
+            lender("Other Bank")
+        );
+        fs::write(&path, both).unwrap();
+        let summary = registry.reload().unwrap();
+        assert_eq!(summary.added, ["Other Bank"]);
+        assert!(registry.reload().unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use rand::Rng;
+
+use super::error::{Result, SupremoError};
+
+/// Lender endpoints called by [`LoanClient`](super::client::LoanClient).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    AuthToken,
+    ExchangeCode,
+    RefreshToken,
+    RevokeToken,
+    IntrospectToken,
+    UserInfo,
+    ClientLimit,
+    Anchors,
+    CalculateLoan,
+    ApplyLoan,
+}
+
+impl Endpoint {
+    pub const ALL: [Endpoint; 10] = [
+        Endpoint::AuthToken,
+        Endpoint::ExchangeCode,
+        Endpoint::RefreshToken,
+        Endpoint::RevokeToken,
+        Endpoint::IntrospectToken,
+        Endpoint::UserInfo,
+        Endpoint::ClientLimit,
+        Endpoint::Anchors,
+        Endpoint::CalculateLoan,
+        Endpoint::ApplyLoan,
+    ];
+
+    /// sending the request twice has the same effect as sending it once
+    ///
+    /// authorization codes and rotated refresh tokens can only be used once and every
+    /// loan application creates a loan, so those are only safe to resend with an
+    /// idempotency key
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(
+            self,
+            Endpoint::ExchangeCode | Endpoint::RefreshToken | Endpoint::ApplyLoan
+        )
+    }
+}
+
+/// When and how often failed requests are sent again.
+///
+/// Only transient failures (connection errors, timeouts, 429 and 5xx responses)
+/// are retried. Idempotent endpoints retry by default, `apply_for_loan` only
+/// retries when the request carries an idempotency key.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use supremo_loan::api::retry::{Endpoint, RetryPolicy};
+///
+/// let policy = RetryPolicy::default()
+///     .max_attempts(5)
+///     .backoff(Duration::from_millis(100), Duration::from_secs(2))
+///     .without_endpoint(Endpoint::CalculateLoan);
+/// assert!(policy.retries(Endpoint::ClientLimit, false));
+/// assert!(!policy.retries(Endpoint::CalculateLoan, false));
+/// assert!(!policy.retries(Endpoint::ApplyLoan, false));
+/// assert!(policy.retries(Endpoint::ApplyLoan, true));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: bool,
+    respect_retry_after: bool,
+    max_retry_after: Duration,
+    endpoints: HashSet<Endpoint>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: true,
+            respect_retry_after: true,
+            max_retry_after: Duration::from_secs(30),
+            endpoints: Endpoint::ALL.iter().copied().collect(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// send every request once
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// total number of attempts including the first one
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// delay before the first retry and the largest delay between two attempts
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// growth factor of the delay after every failed attempt, must be finite and at
+    /// least `1.0`
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// randomize delays so many clients failing at once do not retry in lockstep
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// wait as long as the lender's `Retry-After` header asks, up to `max`;
+    /// longer requested delays are not retried
+    pub fn respect_retry_after(mut self, respect: bool, max: Duration) -> Self {
+        self.respect_retry_after = respect;
+        self.max_retry_after = max;
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoints.insert(endpoint);
+        self
+    }
+
+    pub fn without_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoints.remove(&endpoint);
+        self
+    }
+
+    /// reject settings that cannot produce a sensible delay
+    pub fn validate(&self) -> Result<()> {
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            return Err(SupremoError::Config(format!(
+                "retry multiplier {} must be a finite number of at least 1.0",
+                self.multiplier
+            )));
+        }
+        Ok(())
+    }
+
+    /// whether failed requests to `endpoint` may be retried at all
+    pub fn retries(&self, endpoint: Endpoint, has_idempotency_key: bool) -> bool {
+        self.max_attempts > 1
+            && self.endpoints.contains(&endpoint)
+            && (endpoint.is_idempotent() || has_idempotency_key)
+    }
+
+    /// delay before retrying after `attempt` failed attempts, `None` when the error
+    /// must not be retried
+    pub fn delay_for(&self, attempt: u32, error: &SupremoError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.is_transient() {
+            return None;
+        }
+        if self.respect_retry_after {
+            if let Some(retry_after) = error.retry_after() {
+                return (retry_after <= self.max_retry_after).then_some(retry_after);
+            }
+        }
+        Some(self.backoff_delay(attempt))
+    }
+
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(32) as i32;
+        // clamp in f64 seconds, the product can overflow `Duration` or be NaN
+        let secs = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = Duration::try_from_secs_f64(secs.min(self.max_backoff.as_secs_f64()))
+            .unwrap_or(self.max_backoff);
+        if self.jitter {
+            // keep at least half of the delay so retries still back off
+            let half = delay / 2;
+            half + half.mul_f64(rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn server_error() -> SupremoError {
+        SupremoError::from_status(StatusCode::BAD_GATEWAY, serde_json::Value::Null)
+    }
+
+    #[test]
+    fn backoff_grows_until_max() {
+        let policy = RetryPolicy::default()
+            .max_attempts(10)
+            .backoff(Duration::from_millis(100), Duration::from_millis(350))
+            .jitter(false);
+        let delays: Vec<_> = (1..5)
+            .map(|attempt| policy.delay_for(attempt, &server_error()).unwrap())
+            .collect();
+        assert_eq!(
+            delays,
+            [100, 200, 350, 350].map(Duration::from_millis).to_vec()
+        );
+        assert_eq!(policy.delay_for(10, &server_error()), None);
+    }
+
+    #[test]
+    fn large_multipliers_stop_at_max() {
+        let policy = RetryPolicy::default()
+            .max_attempts(u32::MAX)
+            .backoff(Duration::from_secs(1), Duration::from_secs(60))
+            .multiplier(10.0)
+            .jitter(false);
+        assert!(policy.validate().is_ok());
+        for attempt in [20, 40, 1_000, u32::MAX - 1] {
+            assert_eq!(
+                policy.delay_for(attempt, &server_error()),
+                Some(Duration::from_secs(60))
+            );
+        }
+        for multiplier in [0.5, -2.0, f64::NAN, f64::INFINITY] {
+            let policy = RetryPolicy::default().multiplier(multiplier);
+            assert!(policy.validate().is_err(), "{}", multiplier);
+            assert!(policy.delay_for(2, &server_error()).is_some());
+        }
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy =
+            RetryPolicy::default().backoff(Duration::from_millis(100), Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = policy.delay_for(2, &server_error()).unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let policy = RetryPolicy::default();
+        let error = SupremoError::from_status(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({"code": "insufficient_limit"}),
+        );
+        assert_eq!(policy.delay_for(1, &error), None);
+        assert!(!policy.retries(Endpoint::ExchangeCode, false));
+        assert!(!RetryPolicy::none().retries(Endpoint::ClientLimit, false));
+    }
+}
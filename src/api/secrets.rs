@@ -0,0 +1,636 @@
+//! Sources for lender keys.
+//!
+//! A [`SecretProvider`] looks up the public or secret key of a lender by its name.
+//! [`EnvProvider`] keeps the `{NAME}_SECRET_KEY` convention of
+//! [`add_clients_keys`](super::actions::add_clients_keys) with a configurable template,
+//! [`SecretFilesProvider`] reads mounted secret files (Docker and Kubernetes secrets),
+//! [`DotenvProvider`] reads a `.env` file, [`EncryptedKeyfileProvider`] reads an AES-256-GCM
+//! encrypted keyfile and [`ChainProvider`] tries several of them in order.
+//!
+//! Keys and tokens are held in a [`SecretString`], which keeps them out of logs.
+
+#[cfg(feature = "keyfile")]
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+use super::error::{Result, SupremoError};
+
+/// value written in place of redacted secrets
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A key or token that is not printed.
+///
+/// `Debug`, `Display` and `Serialize` show `[REDACTED]`, the value is only readable
+/// through [`SecretString::expose`] and its memory is zeroed when dropped. Fields whose
+/// value must be stored, such as tokens persisted by the application, opt in with
+/// [`serialize_exposed`].
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::secrets::SecretString;
+///
+/// let secret = SecretString::from("secret_key");
+/// assert_eq!(format!("{:?} {}", secret, secret), "[REDACTED] [REDACTED]");
+/// assert_eq!(serde_json::to_string(&secret).unwrap(), r#""[REDACTED]""#);
+/// assert_eq!(secret.expose(), "secret_key");
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    /// the secret value, keep it out of logs
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl PartialEq<str> for SecretString {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for SecretString {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// write the value of the secret, for `#[serde(serialize_with = "serialize_exposed")]`
+/// on fields that must survive a round trip
+pub fn serialize_exposed<S: Serializer>(
+    secret: &SecretString,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(secret.expose())
+}
+
+/// [`serialize_exposed`] for optional secrets
+pub fn serialize_exposed_option<S: Serializer>(
+    secret: &Option<SecretString>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    secret
+        .as_ref()
+        .map(SecretString::expose)
+        .serialize(serializer)
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+/// Key of a lender looked up by a [`SecretProvider`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyKind {
+    PublicKey,
+    SecretKey,
+}
+
+impl KeyKind {
+    /// `public_key` or `secret_key`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyKind::PublicKey => "public_key",
+            KeyKind::SecretKey => "secret_key",
+        }
+    }
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Source of lender keys.
+///
+/// `secret` returns `Ok(None)` when the provider does not hold the key, so a
+/// [`ChainProvider`] moves on to the next provider, and an error when the provider
+/// could not be read. Providers are called synchronously, also on the request path when a
+/// [`CredentialStore`](super::credentials::CredentialStore) reloads, so reads should be cheap.
+pub trait SecretProvider: Send + Sync + fmt::Debug {
+    fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>>;
+
+    /// where the key is looked up, used in error messages
+    fn location(&self, lender: &str, kind: KeyKind) -> String;
+
+    /// the key, or an error naming where it was looked for
+    fn require(&self, lender: &str, kind: KeyKind) -> Result<SecretString> {
+        self.secret(lender, kind)?.ok_or_else(|| {
+            SupremoError::Config(format!("{} is not set", self.location(lender, kind)))
+        })
+    }
+}
+
+/// expand `{NAME}`/`{KEY}` (upper case) and `{name}`/`{key}` (lower case), spaces become `_`
+fn expand(template: &str, lender: &str, kind: KeyKind) -> String {
+    let name = lender.replace(' ', "_");
+    template
+        .replace("{NAME}", &name.to_uppercase())
+        .replace("{KEY}", &kind.as_str().to_uppercase())
+        .replace("{name}", &name.to_lowercase())
+        .replace("{key}", kind.as_str())
+}
+
+/// Keys in environment variables named after a template.
+///
+/// The default template `{NAME}_{KEY}` reads `BANK_NAME_SECRET_KEY` for the secret key of
+/// `bank name`.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::secrets::{EnvProvider, KeyKind, SecretProvider};
+///
+/// let provider = EnvProvider::with_prefix("SUPREMO_");
+/// assert_eq!(
+///     provider.location("bank name", KeyKind::SecretKey),
+///     "env value SUPREMO_BANK_NAME_SECRET_KEY"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct EnvProvider {
+    template: String,
+}
+
+impl Default for EnvProvider {
+    fn default() -> Self {
+        Self::new("{NAME}_{KEY}")
+    }
+}
+
+impl EnvProvider {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// `{prefix}{NAME}_{KEY}`
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self::new(format!("{}{{NAME}}_{{KEY}}", prefix))
+    }
+
+    fn var(&self, lender: &str, kind: KeyKind) -> String {
+        expand(&self.template, lender, kind)
+    }
+}
+
+impl SecretProvider for EnvProvider {
+    fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+        Ok(std::env::var(self.var(lender, kind))
+            .ok()
+            .map(SecretString::from))
+    }
+
+    fn location(&self, lender: &str, kind: KeyKind) -> String {
+        format!("env value {}", self.var(lender, kind))
+    }
+}
+
+/// Keys in files under a directory, one key per file, as mounted by Docker and Kubernetes.
+///
+/// The default template `{name}_{key}` reads `/run/secrets/bank_name_secret_key`, use
+/// `{name}/{key}` for a directory per lender. Surrounding whitespace is ignored.
+#[derive(Debug, Clone)]
+pub struct SecretFilesProvider {
+    dir: PathBuf,
+    template: String,
+}
+
+impl SecretFilesProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            template: "{name}_{key}".to_string(),
+        }
+    }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    fn path(&self, lender: &str, kind: KeyKind) -> PathBuf {
+        self.dir.join(expand(&self.template, lender, kind))
+    }
+}
+
+impl SecretProvider for SecretFilesProvider {
+    fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+        let path = self.path(lender, kind);
+        match fs::read_to_string(&path) {
+            Ok(mut key) => {
+                let secret = SecretString::from(key.trim());
+                key.zeroize();
+                Ok(Some(secret))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(SupremoError::Config(format!(
+                "could not read {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    fn location(&self, lender: &str, kind: KeyKind) -> String {
+        format!("secret file {}", self.path(lender, kind).display())
+    }
+}
+
+/// Keys in a `.env` file, named like [`EnvProvider`].
+///
+/// Lines are `NAME=value`, optionally prefixed with `export` and quoted, `#` starts a
+/// comment. The file is read once, when the provider is created.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::secrets::{DotenvProvider, KeyKind, SecretProvider};
+///
+/// let provider = DotenvProvider::parse(
+///     "# lender keys\nexport BANK_NAME_SECRET_KEY=\"secret key\"\nBANK_NAME_PUBLIC_KEY=public_key # staging\n",
+/// );
+/// assert_eq!(
+///     provider.require("bank name", KeyKind::SecretKey).unwrap(),
+///     "secret key"
+/// );
+/// assert_eq!(
+///     provider.require("bank name", KeyKind::PublicKey).unwrap(),
+///     "public_key"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct DotenvProvider {
+    source: String,
+    values: HashMap<String, SecretString>,
+    template: String,
+}
+
+impl DotenvProvider {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut content = fs::read_to_string(path).map_err(|e| {
+            SupremoError::Config(format!("could not read {}: {}", path.display(), e))
+        })?;
+        let mut provider = Self::parse(&content);
+        content.zeroize();
+        provider.source = path.display().to_string();
+        Ok(provider)
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let values = content
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let line = line.strip_prefix("export ").unwrap_or(line);
+                let (name, value) = line.split_once('=')?;
+                Some((name.trim().to_string(), unquote(value.trim()).into()))
+            })
+            .collect();
+        Self {
+            source: ".env".to_string(),
+            values,
+            template: "{NAME}_{KEY}".to_string(),
+        }
+    }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+}
+
+/// strip matching quotes, or a trailing ` #` comment from unquoted values
+fn unquote(value: &str) -> String {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote) {
+            if let Some(end) = inner.find(quote) {
+                return inner[..end].to_string();
+            }
+        }
+    }
+    match value.find(" #") {
+        Some(comment) => value[..comment].trim_end().to_string(),
+        None => value.to_string(),
+    }
+}
+
+impl SecretProvider for DotenvProvider {
+    fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+        Ok(self
+            .values
+            .get(&expand(&self.template, lender, kind))
+            .cloned())
+    }
+
+    fn location(&self, lender: &str, kind: KeyKind) -> String {
+        format!(
+            "{} entry {}",
+            self.source,
+            expand(&self.template, lender, kind)
+        )
+    }
+}
+
+/// Keys in a local keyfile encrypted with AES-256-GCM.
+///
+/// The keyfile holds the base64 of a random 12 byte nonce followed by the ciphertext of
+/// a json object mapping lender names to their `public_key` and `secret_key`. Create it
+/// with [`EncryptedKeyfileProvider::write`].
+///
+/// # Examples
+/// ```
+/// use std::collections::BTreeMap;
+/// use supremo_loan::api::secrets::{EncryptedKeyfileProvider, KeyKind, SecretProvider};
+///
+/// let path = std::env::temp_dir().join("supremo-doc-keyfile");
+/// let master_key = [7u8; 32];
+/// let mut keys = BTreeMap::new();
+/// keys.insert(
+///     "bank name".to_string(),
+///     BTreeMap::from([("secret_key".to_string(), "secret_key".into())]),
+/// );
+/// EncryptedKeyfileProvider::write(&path, &master_key, &keys).unwrap();
+///
+/// let provider = EncryptedKeyfileProvider::open(&path, &master_key).unwrap();
+/// assert_eq!(provider.require("bank name", KeyKind::SecretKey).unwrap(), "secret_key");
+/// assert!(EncryptedKeyfileProvider::open(&path, &[8u8; 32]).is_err());
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[cfg(feature = "keyfile")]
+#[derive(Clone)]
+pub struct EncryptedKeyfileProvider {
+    source: String,
+    keys: BTreeMap<String, BTreeMap<String, SecretString>>,
+}
+
+#[cfg(feature = "keyfile")]
+impl fmt::Debug for EncryptedKeyfileProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // list the lenders without their keys
+        f.debug_struct("EncryptedKeyfileProvider")
+            .field("source", &self.source)
+            .field("lenders", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(feature = "keyfile")]
+impl EncryptedKeyfileProvider {
+    const NONCE_LEN: usize = 12;
+
+    /// decrypt `path` with the 32 byte `master_key`
+    pub fn open(path: impl AsRef<Path>, master_key: &[u8]) -> Result<Self> {
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let path = path.as_ref();
+        let cipher = Aes256Gcm::new_from_slice(master_key)
+            .map_err(|_| SupremoError::Config("the keyfile key must be 32 bytes".to_string()))?;
+        let content = fs::read_to_string(path).map_err(|e| {
+            SupremoError::Config(format!("could not read {}: {}", path.display(), e))
+        })?;
+        let invalid = || SupremoError::Config(format!("{} is not a valid keyfile", path.display()));
+        let bytes = STANDARD.decode(content.trim()).map_err(|_| invalid())?;
+        if bytes.len() < Self::NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = bytes.split_at(Self::NONCE_LEN);
+        let mut plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                SupremoError::Config(format!(
+                    "could not decrypt {}, the key is wrong or the file was modified",
+                    path.display()
+                ))
+            })?;
+        let keys = serde_json::from_slice(&plaintext);
+        plaintext.zeroize();
+        let keys = keys.map_err(|_| invalid())?;
+        Ok(Self {
+            source: path.display().to_string(),
+            keys,
+        })
+    }
+
+    /// like [`EncryptedKeyfileProvider::open`], reading the master key as base64 from the
+    /// environment variable `var`
+    pub fn open_with_env_key(path: impl AsRef<Path>, var: &str) -> Result<Self> {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let encoded = std::env::var(var)
+            .map_err(|_| SupremoError::Config(format!("env value {} is not set", var)))?;
+        let master_key = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| SupremoError::Config(format!("env value {} is not base64", var)))?;
+        Self::open(path, &master_key)
+    }
+
+    /// encrypt `keys` (lender name to `public_key`/`secret_key`) into `path`
+    pub fn write(
+        path: impl AsRef<Path>,
+        master_key: &[u8],
+        keys: &BTreeMap<String, BTreeMap<String, SecretString>>,
+    ) -> Result<()> {
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use rand::RngCore;
+
+        let path = path.as_ref();
+        let cipher = Aes256Gcm::new_from_slice(master_key)
+            .map_err(|_| SupremoError::Config("the keyfile key must be 32 bytes".to_string()))?;
+        let mut nonce = [0u8; Self::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let keys: BTreeMap<&str, BTreeMap<&str, &str>> = keys
+            .iter()
+            .map(|(lender, keys)| {
+                let keys = keys
+                    .iter()
+                    .map(|(kind, key)| (kind.as_str(), key.expose()))
+                    .collect();
+                (lender.as_str(), keys)
+            })
+            .collect();
+        let mut plaintext = serde_json::to_vec(&keys).expect("keys serialize to json");
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_slice());
+        plaintext.zeroize();
+        let ciphertext = ciphertext
+            .map_err(|_| SupremoError::Config("could not encrypt the keyfile".to_string()))?;
+
+        let mut bytes = nonce.to_vec();
+        bytes.extend(ciphertext);
+        fs::write(path, STANDARD.encode(bytes))
+            .map_err(|e| SupremoError::Config(format!("could not write {}: {}", path.display(), e)))
+    }
+}
+
+#[cfg(feature = "keyfile")]
+impl SecretProvider for EncryptedKeyfileProvider {
+    fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+        Ok(self
+            .keys
+            .get(lender)
+            .and_then(|keys| keys.get(kind.as_str()))
+            .cloned())
+    }
+
+    fn location(&self, lender: &str, kind: KeyKind) -> String {
+        format!("keyfile {} entry {:?}.{}", self.source, lender, kind)
+    }
+}
+
+/// Providers tried in order, the first one holding the key wins.
+///
+/// An error from a provider (e.g. an unreadable file) is returned rather than skipped,
+/// so a broken source does not silently fall back to another.
+///
+/// # Examples
+/// ```
+/// use supremo_loan::api::secrets::{ChainProvider, DotenvProvider, EnvProvider, KeyKind, SecretProvider};
+///
+/// let provider = ChainProvider::new()
+///     .with(EnvProvider::with_prefix("SUPREMO_DOC_"))
+///     .with(DotenvProvider::parse("BANK_NAME_PUBLIC_KEY=from_dotenv"));
+/// assert_eq!(provider.require("bank name", KeyKind::PublicKey).unwrap(), "from_dotenv");
+///
+/// let err = provider.require("bank name", KeyKind::SecretKey).unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "invalid configuration: env value SUPREMO_DOC_BANK_NAME_SECRET_KEY, \
+///      .env entry BANK_NAME_SECRET_KEY is not set"
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct ChainProvider {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl ChainProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, provider: impl SecretProvider + 'static) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+}
+
+impl SecretProvider for ChainProvider {
+    fn secret(&self, lender: &str, kind: KeyKind) -> Result<Option<SecretString>> {
+        for provider in &self.providers {
+            if let Some(secret) = provider.secret(lender, kind)? {
+                return Ok(Some(secret));
+            }
+        }
+        Ok(None)
+    }
+
+    fn location(&self, lender: &str, kind: KeyKind) -> String {
+        let locations: Vec<String> = self
+            .providers
+            .iter()
+            .map(|provider| provider.location(lender, kind))
+            .collect();
+        locations.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_files_are_read_by_template() {
+        let dir = std::env::temp_dir().join(format!("supremo-secrets-{}", std::process::id()));
+        fs::create_dir_all(dir.join("bank_name")).unwrap();
+        fs::write(dir.join("bank_name_secret_key"), "flat\n").unwrap();
+        fs::write(dir.join("bank_name").join("secret_key"), "nested\n").unwrap();
+
+        let flat = SecretFilesProvider::new(&dir);
+        assert_eq!(
+            flat.secret("Bank Name", KeyKind::SecretKey).unwrap(),
+            Some("flat".into())
+        );
+        assert_eq!(flat.secret("Bank Name", KeyKind::PublicKey).unwrap(), None);
+
+        let nested = SecretFilesProvider::new(&dir).with_template("{name}/{key}");
+        let chain = ChainProvider::new()
+            .with(EnvProvider::with_prefix("SUPREMO_SECRETS_TEST_"))
+            .with(nested)
+            .with(flat);
+        assert_eq!(
+            chain.require("bank name", KeyKind::SecretKey).unwrap(),
+            "nested"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dotenv_values_are_unquoted() {
+        assert_eq!(unquote("'single # kept'"), "single # kept");
+        assert_eq!(unquote("plain # comment"), "plain");
+        assert_eq!(unquote("\"\""), "");
+    }
+}
@@ -0,0 +1,152 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+use super::error::Result;
+use super::secrets::SecretString;
+use super::types::OuathToken;
+
+#[derive(Debug)]
+struct CachedToken {
+    access_token: SecretString,
+    expires_at: Instant,
+}
+
+/// Cache for the client-credentials token of a [`LoanClient`](super::client::LoanClient).
+///
+/// The token is fetched on first use and refreshed `refresh_before` ahead of its
+/// expiry. Concurrent callers wait for a single refresh instead of each fetching
+/// their own token. Clones share the same cached token.
+#[derive(Debug, Clone)]
+pub struct TokenManager {
+    cached: Arc<Mutex<Option<CachedToken>>>,
+    refresh_before: Duration,
+}
+
+impl Default for TokenManager {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(60))
+    }
+}
+
+impl TokenManager {
+    pub fn new(refresh_before: Duration) -> Self {
+        Self {
+            cached: Arc::new(Mutex::new(None)),
+            refresh_before,
+        }
+    }
+
+    /// return the cached token, calling `fetch` when there is none or it is about to expire
+    pub(crate) async fn token<F, Fut>(&self, fetch: F) -> Result<SecretString>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<OuathToken>>,
+    {
+        // holding the lock while fetching makes concurrent callers share one refresh
+        let mut cached = self.cached.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if Instant::now() + self.refresh_before < token.expires_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let token = fetch().await?;
+        let expires_in = Duration::from_secs(token.expires_in.max(0) as u64);
+        *cached = Some(CachedToken {
+            access_token: token.access_token.clone(),
+            expires_at: Instant::now() + expires_in,
+        });
+        Ok(token.access_token)
+    }
+
+    /// drop `access_token` after the lender rejected it, unless it was already replaced
+    pub(crate) async fn invalidate(&self, access_token: &str) {
+        let mut cached = self.cached.lock().await;
+        if cached
+            .as_ref()
+            .is_some_and(|token| token.access_token.expose() == access_token)
+        {
+            *cached = None;
+        }
+    }
+
+    /// forget the cached token, the next call fetches a new one
+    pub async fn clear(&self) {
+        *self.cached.lock().await = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn token(access_token: &str, expires_in: i32) -> OuathToken {
+        OuathToken {
+            access_token: access_token.into(),
+            token_type: "Bearer".to_string(),
+            expires_in,
+            scope: "read".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_until_close_to_expiry() {
+        let manager = TokenManager::new(Duration::from_secs(60));
+        let fetches = AtomicUsize::new(0);
+        let fetch = |expires_in| {
+            let n = fetches.fetch_add(1, Ordering::SeqCst);
+            async move { Ok(token(&format!("token-{}", n), expires_in)) }
+        };
+
+        assert_eq!(manager.token(|| fetch(3600)).await.unwrap(), "token-0");
+        assert_eq!(manager.token(|| fetch(3600)).await.unwrap(), "token-0");
+
+        // expires within the refresh window, so the next call refreshes
+        manager.clear().await;
+        assert_eq!(manager.token(|| fetch(30)).await.unwrap(), "token-1");
+        assert_eq!(manager.token(|| fetch(3600)).await.unwrap(), "token-2");
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_refresh() {
+        let manager = TokenManager::default();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let calls = (0..8).map(|_| {
+            let manager = manager.clone();
+            let fetches = fetches.clone();
+            tokio::spawn(async move {
+                manager
+                    .token(|| async move {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        Ok(token("shared", 3600))
+                    })
+                    .await
+            })
+        });
+        for call in calls.collect::<Vec<_>>() {
+            assert_eq!(call.await.unwrap().unwrap(), "shared");
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn invalidate_keeps_newer_token() {
+        let manager = TokenManager::default();
+        manager
+            .token(|| async { Ok(token("new", 3600)) })
+            .await
+            .unwrap();
+        manager.invalidate("old").await;
+        let current = manager
+            .token(|| async { Ok(token("other", 3600)) })
+            .await
+            .unwrap();
+        assert_eq!(current, "new");
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+
+use super::cassette::Cassette;
+use super::error::{Result, SupremoError};
+
+const DEFAULT_USER_AGENT: &str = concat!("supremo_loan/", env!("CARGO_PKG_VERSION"));
+
+/// Shared HTTP transport used by [`LoanClient`](super::client::LoanClient).
+///
+/// The transport owns a single connection pool, so cloning it (or the client
+/// holding it) reuses open connections and TLS sessions instead of opening new ones.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use supremo_loan::api::transport::Transport;
+///
+/// let transport = Transport::builder()
+///     .connect_timeout(Duration::from_secs(5))
+///     .timeout(Duration::from_secs(30))
+///     .pool_max_idle_per_host(16)
+///     .user_agent("my-app/1.0")
+///     .default_header("x-partner-id", "partner-1")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Transport {
+    client: reqwest::Client,
+    cassette: Option<Cassette>,
+}
+
+/// response with its body read, as returned by the lender or a cassette
+#[derive(Debug)]
+pub(crate) struct RawResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Transport {
+    pub fn builder() -> TransportBuilder {
+        TransportBuilder::default()
+    }
+
+    /// use an already configured `reqwest::Client`
+    pub fn from_client(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            cassette: None,
+        }
+    }
+
+    /// record requests to, or replay responses from, `cassette`
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    pub(crate) fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub(crate) async fn send(&self, request: reqwest::RequestBuilder) -> Result<RawResponse> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        if let Some(cassette) = &self.cassette {
+            return cassette.send(&client, request).await;
+        }
+
+        let response = client.execute(request).await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?.to_vec();
+        Ok(RawResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::builder()
+            .build()
+            .expect("default transport configuration is valid")
+    }
+}
+
+#[derive(Debug)]
+pub struct TransportBuilder {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    proxies: Vec<String>,
+    no_proxy: bool,
+    root_certificates: Vec<Vec<u8>>,
+    user_agent: String,
+    default_headers: Vec<(String, String)>,
+}
+
+impl Default for TransportBuilder {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            proxies: Vec::new(),
+            no_proxy: false,
+            root_certificates: Vec::new(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            default_headers: Vec::new(),
+        }
+    }
+}
+
+impl TransportBuilder {
+    /// time allowed to establish a connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// time allowed for the whole request, from connecting until the response body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// send all requests through the given proxy (`http://`, `https://` or `socks5://`)
+    pub fn proxy(mut self, proxy_url: &str) -> Self {
+        self.proxies.push(proxy_url.to_string());
+        self
+    }
+
+    /// ignore proxies configured through `HTTP_PROXY`/`HTTPS_PROXY`
+    pub fn no_proxy(mut self) -> Self {
+        self.no_proxy = true;
+        self
+    }
+
+    /// trust an extra PEM encoded root certificate, e.g. a lender's private CA
+    pub fn add_root_certificate_pem(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// header sent with every request
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.default_headers
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn build(self) -> Result<Transport> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.default_headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                SupremoError::Config(format!("invalid header name {}: {}", name, e))
+            })?;
+            let value = HeaderValue::from_str(value).map_err(|e| {
+                SupremoError::Config(format!("invalid value for header {}: {}", name, e))
+            })?;
+            headers.insert(name, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .user_agent(self.user_agent)
+            .default_headers(headers)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive);
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if self.no_proxy {
+            builder = builder.no_proxy();
+        }
+        for proxy_url in &self.proxies {
+            let proxy = reqwest::Proxy::all(proxy_url)
+                .map_err(|e| SupremoError::Config(format!("invalid proxy {}: {}", proxy_url, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        for pem in &self.root_certificates {
+            let certificate = reqwest::Certificate::from_pem(pem)
+                .map_err(|e| SupremoError::Config(format!("invalid root certificate: {}", e)))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+        if !self.root_certificates.is_empty() {
+            return Err(SupremoError::Config(
+                "root certificates need the native-tls or rustls feature".to_string(),
+            ));
+        }
+
+        let client = builder
+            .build()
+            .map_err(|e| SupremoError::Config(format!("could not build http client: {}", e)))?;
+        Ok(Transport::from_client(client))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_with_defaults() {
+        assert!(Transport::builder().build().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let err = Transport::builder()
+            .default_header("bad header", "value")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+
+        let err = Transport::builder().proxy("not a url").build().unwrap_err();
+        assert!(matches!(err, SupremoError::Config(_)));
+    }
+}
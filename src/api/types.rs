@@ -1,5 +1,9 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
 
+use super::secrets::{serialize_exposed, SecretString};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AnchorPagination {
     pub page: Option<i32>,      // default 1
@@ -7,9 +11,8 @@ pub struct AnchorPagination {
     pub order: Option<String>,  // "-id" or "id"
 }
 
-// impl default for AnchorPagination
-impl AnchorPagination {
-    pub fn default() -> Self {
+impl Default for AnchorPagination {
+    fn default() -> Self {
         AnchorPagination {
             page: Some(1),
             page_size: Some(20),
@@ -18,7 +21,7 @@ impl AnchorPagination {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Anchor {
     pub anchor_id: i32,
     pub business_logo: Option<String>,
@@ -32,7 +35,7 @@ pub struct Anchor {
     pub updated_at: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PaginatedAnchors {
     pub data: Vec<Anchor>,
     pub page: i32,
@@ -40,7 +43,7 @@ pub struct PaginatedAnchors {
     pub total: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LoanInput {
     pub amount: f64,
     pub anchor_id: i32,
@@ -50,7 +53,7 @@ pub struct LoanInput {
     pub metadata: serde_json::Value,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CalculateLonaResponse {
     pub excise_duty: f64,
     pub facility_fee: f64,
@@ -61,7 +64,7 @@ pub struct CalculateLonaResponse {
     pub total: f64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientLimit {
     pub remaining_limit: f64,
     pub total_limit: f64,
@@ -73,7 +76,7 @@ pub struct LoanCreted {
     pub message: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct OuathUser {
     pub id: i32,
     pub email: String,
@@ -83,8 +86,11 @@ pub struct OuathUser {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OuathCode {
-    pub access_token: String,
-    pub refresh_token: String,
+    #[serde(serialize_with = "serialize_exposed")]
+    pub access_token: SecretString,
+    /// empty when the lender does not rotate the refresh token on refresh
+    #[serde(default, serialize_with = "serialize_exposed")]
+    pub refresh_token: SecretString,
     pub token_type: String,
     pub expires_in: i32,
     pub scope: String,
@@ -92,8 +98,194 @@ pub struct OuathCode {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OuathToken {
-    pub access_token: String,
+    #[serde(serialize_with = "serialize_exposed")]
+    pub access_token: SecretString,
     pub token_type: String,
     pub expires_in: i32,
     pub scope: String,
 }
+
+/// Kind of token passed to revocation and introspection, helps the lender find it faster.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+}
+
+impl TokenTypeHint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenTypeHint::AccessToken => "access_token",
+            TokenTypeHint::RefreshToken => "refresh_token",
+        }
+    }
+}
+
+/// Token metadata returned by the introspection endpoint (RFC 7662).
+///
+/// Only `active` is guaranteed, inactive tokens usually come without any other field.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub token_type: Option<String>,
+    /// unix timestamp (seconds) at which the token expires
+    #[serde(default)]
+    pub exp: Option<i64>,
+    /// unix timestamp (seconds) at which the token was issued
+    #[serde(default)]
+    pub iat: Option<i64>,
+    /// subject of the token, usually the borrower's id
+    #[serde(default)]
+    pub sub: Option<String>,
+}
+
+impl TokenIntrospection {
+    pub fn scopes(&self) -> Vec<&str> {
+        self.scope
+            .as_deref()
+            .map(|scope| scope.split_whitespace().collect())
+            .unwrap_or_default()
+    }
+
+    /// active and not past its expiry
+    pub fn is_valid(&self) -> bool {
+        self.active && !matches!(self.exp, Some(exp) if exp <= unix_now() as i64)
+    }
+}
+
+/// Tokens issued to a borrower through the authorization-code or refresh-token grant.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserToken {
+    #[serde(serialize_with = "serialize_exposed")]
+    pub access_token: SecretString,
+    #[serde(serialize_with = "serialize_exposed")]
+    pub refresh_token: SecretString,
+    pub token_type: String,
+    pub expires_in: i32,
+    /// unix timestamp (seconds) at which the access token expires
+    pub expires_at: u64,
+    pub scope: String,
+}
+
+impl UserToken {
+    pub fn from_code(code: OuathCode) -> Self {
+        let expires_at = unix_now() + code.expires_in.max(0) as u64;
+        UserToken {
+            access_token: code.access_token,
+            refresh_token: code.refresh_token,
+            token_type: code.token_type,
+            expires_in: code.expires_in,
+            expires_at,
+            scope: code.scope,
+        }
+    }
+
+    /// whether the access token expires within `leeway`
+    pub fn is_expired(&self, leeway: Duration) -> bool {
+        unix_now() + leeway.as_secs() >= self.expires_at
+    }
+}
+
+/// Borrower linked through the authorization-code flow with the tokens to act on their behalf.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSession {
+    pub user: OuathUser,
+    pub token: UserToken,
+}
+
+/// Borrower account linked to a lender, meant to be persisted by the application and
+/// re-validated with [`LoanClient::revalidate_account`](super::client::LoanClient::revalidate_account).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkedAccount {
+    pub lender_name: String,
+    pub user: OuathUser,
+    pub token: UserToken,
+    /// unix timestamp (seconds) at which the account was linked
+    pub linked_at: u64,
+}
+
+impl LinkedAccount {
+    pub fn new(lender_name: &str, session: UserSession) -> Self {
+        LinkedAccount {
+            lender_name: lender_name.to_string(),
+            user: session.user,
+            token: session.token,
+            linked_at: unix_now(),
+        }
+    }
+
+    /// differences between the stored profile and `current`
+    ///
+    /// # Examples
+    /// ```
+    /// use supremo_loan::api::types::{AccountChange, LinkedAccount, OuathUser, UserToken};
+    ///
+    /// let user = OuathUser {
+    ///     id: 1,
+    ///     email: "borrower@example.com".to_string(),
+    ///     company_name: "Shop".to_string(),
+    ///     anchor_id: 1,
+    /// };
+    /// let account = LinkedAccount {
+    ///     lender_name: "bank name".to_string(),
+    ///     user: user.clone(),
+    ///     token: UserToken {
+    ///         access_token: "access".into(),
+    ///         refresh_token: "refresh".into(),
+    ///         token_type: "Bearer".to_string(),
+    ///         expires_in: 3600,
+    ///         expires_at: 0,
+    ///         scope: "read".to_string(),
+    ///     },
+    ///     linked_at: 0,
+    /// };
+    /// let current = OuathUser { anchor_id: 2, ..user };
+    /// assert_eq!(account.changes(&current), vec![AccountChange::AnchorId { old: 1, new: 2 }]);
+    /// ```
+    pub fn changes(&self, current: &OuathUser) -> Vec<AccountChange> {
+        let mut changes = Vec::new();
+        if self.user.anchor_id != current.anchor_id {
+            changes.push(AccountChange::AnchorId {
+                old: self.user.anchor_id,
+                new: current.anchor_id,
+            });
+        }
+        if self.user.company_name != current.company_name {
+            changes.push(AccountChange::CompanyName {
+                old: self.user.company_name.clone(),
+                new: current.company_name.clone(),
+            });
+        }
+        if self.user.email != current.email {
+            changes.push(AccountChange::Email {
+                old: self.user.email.clone(),
+                new: current.email.clone(),
+            });
+        }
+        changes
+    }
+}
+
+/// Change in a borrower's profile found while re-validating a [`LinkedAccount`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "field", rename_all = "snake_case")]
+pub enum AccountChange {
+    AnchorId { old: i32, new: i32 },
+    CompanyName { old: String, new: String },
+    Email { old: String, new: String },
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}